@@ -0,0 +1,142 @@
+use crate::{State, edit::Position};
+
+/// Returns the bracket paired with `c` and whether it comes after `c`
+const fn partner(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
+impl State {
+    /// Returns the position of the bracket matching the one at `pos`, across lines
+    /// Brackets of the same kind in between must be balanced
+    pub fn matching_bracket(&self, (line, col): Position) -> Option<Position> {
+        let bracket = self.text_lines.get(line)?.chars().nth(col)?;
+        let (target, forward) = partner(bracket)?;
+
+        let mut depth = 0usize;
+        // Returns true on the matching bracket
+        let mut matches = |c: char| {
+            if c == bracket {
+                depth += 1;
+            } else if c == target {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        };
+
+        if forward {
+            for (index, text) in self.text_lines.iter().enumerate().skip(line) {
+                let from = if index == line { col + 1 } else { 0 };
+                if let Some((i, _)) = text.char_enumerate().skip(from).find(|&(_, c)| matches(c)) {
+                    return Some((index, i));
+                }
+            }
+        } else {
+            for index in (0..=line).rev() {
+                let text = &self.text_lines[index];
+                let from = if index == line { col } else { text.len() };
+                let mut chars = (0..from).rev().zip(text.rchars_from(from));
+                if let Some((i, _)) = chars.find(|&(_, c)| matches(c)) {
+                    return Some((index, i));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Moves to the bracket matching the first one at or after the cursor on its line, like `%`
+    /// Returns true if the cursor moved
+    pub fn jump_to_matching_bracket(&mut self) -> bool {
+        let current = self.current_line_index();
+        let Some((col, _)) = self.text_lines[current]
+            .char_enumerate()
+            .skip(self.cursor_pos.col)
+            .find(|&(_, c)| partner(c).is_some())
+        else {
+            return false;
+        };
+        let Some((line, col)) = self.matching_bracket((current, col)) else {
+            return false;
+        };
+
+        self.push_jump();
+        self.move_to_line(line);
+        self.cursor_pos.col = col;
+        self.target_col = col;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn matching_bracket() {
+        let state = state_with_lines(&["fn f(a: [u8; 2]) {", "    g((1), \"日\");", "}"]);
+        assert_eq!(state.matching_bracket((0, 4)), Some((0, 15)));
+        assert_eq!(state.matching_bracket((0, 15)), Some((0, 4)));
+        assert_eq!(state.matching_bracket((0, 8)), Some((0, 14)));
+        // Nested brackets of the same kind are skipped
+        assert_eq!(state.matching_bracket((1, 5)), Some((1, 14)));
+        assert_eq!(state.matching_bracket((1, 14)), Some((1, 5)));
+        // Across lines
+        assert_eq!(state.matching_bracket((0, 17)), Some((2, 0)));
+        assert_eq!(state.matching_bracket((2, 0)), Some((0, 17)));
+
+        assert_eq!(state.matching_bracket((0, 0)), None);
+        assert_eq!(state_with_lines(&["(()"]).matching_bracket((0, 0)), None);
+    }
+
+    #[test]
+    fn percent() {
+        let mut state = state_with_lines(&["if (a) {", "  b[0]", "}", "none"]);
+        // The first bracket after the cursor is used
+        type_keys(&mut state, &[Key::Char('%')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 5));
+        type_keys(&mut state, &[Key::Char('%')]);
+        assert_eq!(state.cursor_pos.col, 3);
+
+        type_keys(&mut state, &[Key::Char('$'), Key::Char('%')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 0));
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 7));
+
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('$'), Key::Char('%')],
+        );
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 3));
+
+        // No bracket on the line
+        type_keys(&mut state, &[Key::Char('G'), Key::Char('%')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (3, 0));
+
+        // It includes both brackets with an operator
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('k'),
+                Key::Char('k'),
+                Key::Char('$'),
+                Key::Char('d'),
+                Key::Char('%'),
+            ],
+        );
+        assert_eq!(lines(&state), ["if (a) {", "  b", "}", "none"]);
+    }
+}
@@ -1,10 +1,54 @@
-use crate::{MessageType, State, utils::save_to_file};
+use crate::{
+    MessageType, State,
+    config::OptionError,
+    highlight,
+    keymap::{MapError, MapMode},
+    line::Line,
+    utils::{load_file, save_to_file},
+};
 
 #[derive(Debug)]
 pub enum Command {
-    Save { filename: Option<String> },
-    Quit { forcefully: bool },
-    SaveAndQuit { filename: Option<String> },
+    Save {
+        filename: Option<String>,
+    },
+    Quit {
+        forcefully: bool,
+    },
+    SaveAndQuit {
+        filename: Option<String>,
+    },
+    /// Without a filename the current file is read again
+    Edit {
+        filename: Option<String>,
+        forcefully: bool,
+    },
+    Set {
+        options: Vec<String>,
+    },
+    /// 1-indexed and clamped to the buffer, `usize::MAX` is the last line
+    GoToLine {
+        line: usize,
+    },
+    /// Stops highlighting the matches of the last search until the next one
+    NoHighlight,
+    /// `:nmap lhs rhs` binds the key `lhs` to what `rhs` does
+    Map {
+        mode: MapMode,
+        lhs: String,
+        rhs: String,
+    },
+    Unmap {
+        mode: MapMode,
+        lhs: String,
+    },
+    /// `:s/pattern/replacement/g` on the current line, or every line with `:%s`
+    Substitute {
+        whole_buffer: bool,
+        pattern: String,
+        replacement: String,
+        global: bool,
+    },
     None,
 }
 
@@ -12,11 +56,103 @@ pub enum Command {
 pub enum ParseError {
     UnknownCommand(String),
     TrailingCharacters(String),
+    ArgumentRequired,
+}
+
+/// Returns the modes of a mapping command and whether it removes the mapping
+/// Mappings never chain, so the `noremap` forms are the same
+fn map_command(name: &str) -> Option<(MapMode, bool)> {
+    match name {
+        "map" | "noremap" | "no" => Some((MapMode::All, false)),
+        "nmap" | "nm" | "nnoremap" | "nn" => Some((MapMode::Normal, false)),
+        "vmap" | "vm" | "vnoremap" | "vn" => Some((MapMode::Visual, false)),
+        "unmap" | "unm" => Some((MapMode::All, true)),
+        "nunmap" | "nun" => Some((MapMode::Normal, true)),
+        "vunmap" | "vu" => Some((MapMode::Visual, true)),
+        _ => None,
+    }
+}
+
+/// Splits `text` at the `/` not preceded by a backslash, `\/` becomes `/` and `\\` becomes `\`
+fn split_delimited(text: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("there is always a field");
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('/' | '\\')) => field.push(escaped),
+                Some(other) => {
+                    field.push('\\');
+                    field.push(other);
+                }
+                None => field.push('\\'),
+            },
+            '/' => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields
+}
+
+/// Parses `s/pattern/replacement/flags` or `%s/...`, `None` if `input` is another command
+fn parse_substitute(input: &str) -> Option<Result<Command, ParseError>> {
+    let (whole_buffer, rest) = match input.strip_prefix('%') {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let rest = rest
+        .strip_prefix("substitute")
+        .or_else(|| rest.strip_prefix('s'))?
+        .strip_prefix('/')?;
+
+    let mut fields = split_delimited(rest).into_iter();
+    let pattern = fields.next().unwrap_or_default();
+    let replacement = fields.next().unwrap_or_default();
+    let flags = fields.collect::<Vec<_>>().join("/");
+    let global = match flags.as_str() {
+        "" => false,
+        "g" => true,
+        trailing => return Some(Err(ParseError::TrailingCharacters(trailing.to_owned()))),
+    };
+
+    Some(Ok(Command::Substitute {
+        whole_buffer,
+        pattern,
+        replacement,
+        global,
+    }))
 }
 
 impl Command {
     pub fn parse(input: &str) -> Result<Self, ParseError> {
+        // The pattern can contain spaces
+        if let Some(res) = parse_substitute(input.trim_start()) {
+            return res;
+        }
+
         let parts: Vec<&str> = input.split_whitespace().collect();
+        if let Some((name, args)) = parts.split_first()
+            && let Some((mode, unmap)) = map_command(name)
+        {
+            return match (unmap, args) {
+                (false, [lhs, rhs]) => Ok(Command::Map {
+                    mode,
+                    lhs: (*lhs).to_owned(),
+                    rhs: (*rhs).to_owned(),
+                }),
+                (true, [lhs]) => Ok(Command::Unmap {
+                    mode,
+                    lhs: (*lhs).to_owned(),
+                }),
+                (false, [_, _, trailing @ ..]) | (true, [_, trailing @ ..]) => {
+                    Err(ParseError::TrailingCharacters(trailing.join(" ")))
+                }
+                _ => Err(ParseError::ArgumentRequired),
+            };
+        }
+
         match parts.as_slice() {
             ["q" | "quit"] => Ok(Command::Quit { forcefully: false }),
             ["q!" | "quit!"] => Ok(Command::Quit { forcefully: true }),
@@ -31,6 +167,26 @@ impl Command {
             ["wq" | "wq!" | "x", filename @ ..] => Ok(Command::SaveAndQuit {
                 filename: Some(filename.join(" ")),
             }),
+            ["e" | "edit"] => Ok(Command::Edit {
+                filename: None,
+                forcefully: false,
+            }),
+            ["e!" | "edit!"] => Ok(Command::Edit {
+                filename: None,
+                forcefully: true,
+            }),
+            [cmd @ ("e" | "e!" | "edit" | "edit!"), filename @ ..] => Ok(Command::Edit {
+                filename: Some(filename.join(" ")),
+                forcefully: cmd.ends_with('!'),
+            }),
+            ["set" | "se", options @ ..] => Ok(Command::Set {
+                options: options.iter().map(|&o| o.to_owned()).collect(),
+            }),
+            [number] if number.bytes().all(|b| b.is_ascii_digit()) => Ok(Command::GoToLine {
+                line: number.parse().unwrap_or(usize::MAX),
+            }),
+            ["$"] => Ok(Command::GoToLine { line: usize::MAX }),
+            ["noh" | "nohl" | "nohlsearch"] => Ok(Command::NoHighlight),
             [unknown, ..] => Err(ParseError::UnknownCommand((*unknown).to_owned())),
             [] => Ok(Command::None),
         }
@@ -45,11 +201,43 @@ impl State {
                 let path = filename.map(Into::into).or_else(|| self.save_file.clone());
 
                 if let Some(path) = path {
-                    if save_to_file(&path, &self.text_lines).is_ok() {
-                        self.dirty = false;
-                        self.save_file.get_or_insert(path);
-                    } else {
-                        // do something
+                    if self.config.trim_trailing_whitespace {
+                        self.trim_trailing_whitespace();
+                    }
+                    let end_of_line = self.config.fix_end_of_line || self.end_of_line;
+                    // An empty file stays empty
+                    let lines: &[Line] = match self.text_lines.as_slice() {
+                        [line] if self.empty_file && line.is_empty() => &[],
+                        lines => lines,
+                    };
+                    match save_to_file(&path, lines, end_of_line) {
+                        Ok(written) => {
+                            self.message = crate::Message {
+                                msg: format!(
+                                    "\"{}\" {}L, {}B written",
+                                    path.display(),
+                                    lines.len(),
+                                    written
+                                ),
+                                r#type: MessageType::Info,
+                            };
+                            // The first name given to the buffer tells its language
+                            if self.save_file.is_none() {
+                                self.highlighter = highlight::for_path(&path);
+                                self.save_file = Some(path.clone());
+                            }
+                            // Writing a copy to another file leaves the buffer modified
+                            if self.save_file.as_ref() == Some(&path) {
+                                self.dirty = false;
+                                self.history.mark_saved();
+                            }
+                        }
+                        Err(err) => {
+                            self.message = crate::Message {
+                                msg: format!("Can't open file for writing: {err}"),
+                                r#type: MessageType::Error,
+                            };
+                        }
                     }
                 } else {
                     self.message.r#type = MessageType::Error;
@@ -67,7 +255,88 @@ impl State {
             }
             Command::SaveAndQuit { filename } => {
                 self.handle_command(Command::Save { filename });
-                return self.dirty;
+                // Like in Vim, writing a copy to another file still quits
+                return matches!(self.message.r#type, MessageType::Error);
+            }
+            Command::Edit {
+                filename,
+                forcefully,
+            } => {
+                if !forcefully && self.dirty {
+                    self.message.r#type = MessageType::Error;
+                    "No write since last change (add ! to override)"
+                        .clone_into(&mut self.message.msg);
+                    return true;
+                }
+
+                let path = filename.map(Into::into).or_else(|| self.save_file.clone());
+                let Some(path) = path else {
+                    self.message.r#type = MessageType::Error;
+                    "No file name".clone_into(&mut self.message.msg);
+                    return true;
+                };
+
+                match load_file(&path) {
+                    Ok(mut content) => {
+                        let info = std::mem::take(&mut content.info);
+                        self.open_buffer(content, path);
+                        self.message = crate::Message {
+                            msg: info,
+                            r#type: MessageType::Info,
+                        };
+                    }
+                    Err(err) => {
+                        self.message = crate::Message {
+                            msg: format!("\"{}\" {err}", path.display()),
+                            r#type: MessageType::Error,
+                        };
+                    }
+                }
+            }
+            Command::Set { options } => {
+                for option in options {
+                    let Err(err) = self.config.set(&option) else {
+                        continue;
+                    };
+                    let msg = match err {
+                        OptionError::Unknown(name) => format!("Unknown option: {name}"),
+                        OptionError::InvalidArgument(arg) => format!("Invalid argument: {arg}"),
+                    };
+                    self.message = crate::Message {
+                        msg,
+                        r#type: MessageType::Error,
+                    };
+                    break;
+                }
+            }
+            Command::GoToLine { line } => {
+                self.push_jump();
+                self.move_to_line(line.saturating_sub(1));
+                self.cursor_pos.col = self.get_current_line().map_or(0, Line::first_non_blank);
+                self.target_col = self.cursor_pos.col;
+            }
+            Command::NoHighlight => self.highlight_search = false,
+            Command::Map { mode, lhs, rhs } => {
+                let res = self.keymap.map(mode, &lhs, &rhs);
+                self.handle_map_error(res);
+            }
+            Command::Unmap { mode, lhs } => {
+                let res = self.keymap.unmap(mode, &lhs);
+                self.handle_map_error(res);
+            }
+            Command::Substitute {
+                whole_buffer,
+                pattern,
+                replacement,
+                global,
+            } => {
+                let range = if whole_buffer {
+                    0..self.text_lines.len()
+                } else {
+                    let current = self.current_line_index();
+                    current..current + 1
+                };
+                self.substitute(range, &pattern, &replacement, global);
             }
             Command::None => {}
         }
@@ -75,6 +344,19 @@ impl State {
         true
     }
 
+    fn handle_map_error(&mut self, res: Result<(), MapError>) {
+        let msg = match res {
+            Ok(()) => return,
+            Err(MapError::InvalidKey(key)) => format!("Can only map a single key: {key}"),
+            Err(MapError::UnknownTarget(rhs)) => format!("Nothing to map to: {rhs}"),
+            Err(MapError::NoSuchMapping(lhs)) => format!("No such mapping: {lhs}"),
+        };
+        self.message = crate::Message {
+            msg,
+            r#type: MessageType::Error,
+        };
+    }
+
     pub fn handle_parse_error(&mut self, err: ParseError) {
         match err {
             ParseError::UnknownCommand(unknown) => {
@@ -89,13 +371,24 @@ impl State {
                     r#type: crate::MessageType::Error,
                 }
             }
+            ParseError::ArgumentRequired => {
+                self.message = crate::Message {
+                    msg: "Argument required".to_owned(),
+                    r#type: crate::MessageType::Error,
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::command_parser::{Command, ParseError};
+    use crate::{
+        MessageType, State,
+        command_parser::{Command, ParseError},
+        key::Key,
+        test_utils::{lines, state_with_lines, type_keys},
+    };
 
     #[test]
     fn parse_q() {
@@ -149,4 +442,355 @@ mod tests {
         let cmd = Command::parse("x file").unwrap();
         assert!(matches!(cmd, Command::SaveAndQuit { filename: Some(path) } if path == "file" ));
     }
+
+    #[test]
+    fn parse_edit() {
+        let cmd = Command::parse("e").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Edit {
+                filename: None,
+                forcefully: false
+            }
+        ));
+
+        let cmd = Command::parse("e! file").unwrap();
+        assert!(
+            matches!(cmd, Command::Edit { filename: Some(path), forcefully: true } if path == "file")
+        );
+
+        let cmd = Command::parse("edit other file").unwrap();
+        assert!(
+            matches!(cmd, Command::Edit { filename: Some(path), forcefully: false } if path == "other file")
+        );
+    }
+
+    #[test]
+    fn edit_file() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("vim-rs-edit-{}", std::process::id()));
+        std::fs::write(&file, "first\nsecond\n").unwrap();
+        let edit = |forcefully| Command::Edit {
+            filename: Some(file.display().to_string()),
+            forcefully,
+        };
+
+        let mut state = state_with_lines(&["text", "more"]);
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('x')]);
+        state.handle_command(edit(false));
+        assert_eq!(lines(&state), ["text", "ore"]);
+        assert!(matches!(state.message.r#type, MessageType::Error));
+
+        state.handle_command(edit(true));
+        assert_eq!(lines(&state), ["first", "second"]);
+        assert_eq!(state.cursor_pos.row, 0);
+        assert_eq!(state.save_file.as_ref(), Some(&file));
+        assert!(!state.dirty);
+
+        // The history of the previous buffer is gone
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["first", "second"]);
+
+        // Errors keep the current buffer
+        state.handle_command(Command::Edit {
+            filename: Some(dir.display().to_string()),
+            forcefully: false,
+        });
+        assert_eq!(lines(&state), ["first", "second"]);
+        assert!(matches!(state.message.r#type, MessageType::Error));
+
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn set_command() {
+        let mut state = state_with_lines(&["text"]);
+        let cmd = Command::parse("set nonumber ts=8").unwrap();
+        state.handle_command(cmd);
+        assert!(!state.config.show_line_numbers);
+        assert_eq!(state.config.tab_width, 8);
+
+        let cmd = Command::parse("set expandtab unknown relativenumber").unwrap();
+        state.handle_command(cmd);
+        assert!(state.config.expand_tab);
+        assert!(!state.config.relative_line_numbers);
+        assert_eq!(state.message.msg, "Unknown option: unknown");
+    }
+
+    #[test]
+    fn parse_noh() {
+        assert!(matches!(Command::parse("noh"), Ok(Command::NoHighlight)));
+        assert!(matches!(
+            Command::parse("nohlsearch"),
+            Ok(Command::NoHighlight)
+        ));
+    }
+
+    #[test]
+    fn map_keys() {
+        let mut state = state_with_lines(&["one", "two", "three"]);
+        let run = |state: &mut State, command: &str| match Command::parse(command) {
+            Ok(cmd) => {
+                state.handle_command(cmd);
+            }
+            Err(err) => state.handle_parse_error(err),
+        };
+
+        run(&mut state, "nmap s j");
+        run(&mut state, "nnoremap <C-t> gg");
+        type_keys(&mut state, &[Key::Char('s'), Key::Char('s')]);
+        assert_eq!(state.current_line_index(), 2);
+        type_keys(&mut state, &[Key::Ctrl('t')]);
+        assert_eq!(state.current_line_index(), 0);
+
+        run(&mut state, "map X dd");
+        type_keys(&mut state, &[Key::Char('X')]);
+        assert_eq!(lines(&state), ["two", "three"]);
+
+        run(&mut state, "nunmap s");
+        type_keys(&mut state, &[Key::Char('s')]);
+        assert_eq!(state.current_line_index(), 0);
+
+        run(&mut state, "nmap s");
+        assert_eq!(state.message.msg, "Argument required");
+        run(&mut state, "nmap s j k");
+        assert_eq!(state.message.msg, "Trailing characters: k");
+        run(&mut state, "nmap s q!");
+        assert_eq!(state.message.msg, "Nothing to map to: q!");
+        run(&mut state, "nmap <Foo> j");
+        assert_eq!(state.message.msg, "Can only map a single key: <Foo>");
+        run(&mut state, "nunmap s");
+        assert_eq!(state.message.msg, "No such mapping: s");
+    }
+
+    #[test]
+    fn go_to_line() {
+        assert!(matches!(
+            Command::parse("42"),
+            Ok(Command::GoToLine { line: 42 })
+        ));
+        assert!(matches!(
+            Command::parse("$"),
+            Ok(Command::GoToLine { line: usize::MAX })
+        ));
+        assert!(matches!(
+            Command::parse("4a"),
+            Err(ParseError::UnknownCommand(_))
+        ));
+
+        let mut state = state_with_lines(&["first", "  second", "third"]);
+        state.handle_command(Command::parse("2").unwrap());
+        assert_eq!(state.current_line_index(), 1);
+        assert_eq!(state.cursor_pos.col, 2);
+
+        state.handle_command(Command::parse("100").unwrap());
+        assert_eq!(state.current_line_index(), 2);
+        state.handle_command(Command::parse("0").unwrap());
+        assert_eq!(state.current_line_index(), 0);
+        state.handle_command(Command::parse("$").unwrap());
+        assert_eq!(state.current_line_index(), 2);
+
+        // It is a jump
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(state.current_line_index(), 0);
+    }
+
+    #[test]
+    fn parse_substitute() {
+        let cmd = Command::parse("%s/foo bar/baz/g").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Substitute { whole_buffer: true, pattern, replacement, global: true }
+                if pattern == "foo bar" && replacement == "baz"
+        ));
+
+        let cmd = Command::parse(r"s/a\/b/c\\d\n").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Substitute { whole_buffer: false, pattern, replacement, global: false }
+                if pattern == "a/b" && replacement == r"c\d\n"
+        ));
+
+        // The replacement can be left out to delete the pattern
+        let cmd = Command::parse("s/x").unwrap();
+        assert!(matches!(
+            cmd,
+            Command::Substitute { pattern, replacement, .. } if pattern == "x" && replacement.is_empty()
+        ));
+
+        let res = Command::parse("s/a/b/gx");
+        assert!(matches!(res, Err(ParseError::TrailingCharacters(t)) if t == "gx"));
+        let res = Command::parse("s/a/b/g/");
+        assert!(matches!(res, Err(ParseError::TrailingCharacters(_))));
+
+        // Other commands starting with s
+        assert!(matches!(Command::parse("set"), Ok(Command::Set { .. })));
+        assert!(matches!(
+            Command::parse("sx"),
+            Err(ParseError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn substitute_command() {
+        let mut state = state_with_lines(&["a a", "a", "b"]);
+        state.handle_command(Command::parse("s/a/c/").unwrap());
+        assert_eq!(lines(&state), ["c a", "a", "b"]);
+
+        state.handle_command(Command::parse("%s/a/d/g").unwrap());
+        assert_eq!(lines(&state), ["c d", "d", "b"]);
+        assert_eq!(state.message.msg, "2 substitutions on 2 lines");
+        assert_eq!(state.current_line_index(), 1);
+    }
+
+    #[test]
+    fn save_detects_language() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("vim-rs-language-{}.rs", std::process::id()));
+        let copy = dir.join(format!("vim-rs-language-copy-{}.txt", std::process::id()));
+        let mut state = state_with_lines(&["fn main() {}"]);
+        assert!(state.highlighter.is_none());
+
+        state.handle_command(Command::Save {
+            filename: Some(file.display().to_string()),
+        });
+        assert!(state.highlighter.is_some());
+        // A copy does not rename the buffer
+        state.handle_command(Command::Save {
+            filename: Some(copy.display().to_string()),
+        });
+        assert!(state.highlighter.is_some());
+
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(copy);
+    }
+
+    #[test]
+    fn modified_flag() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("vim-rs-modified-{}", std::process::id()));
+        let copy = dir.join(format!("vim-rs-modified-copy-{}", std::process::id()));
+
+        let mut state = state_with_lines(&["text"]);
+        assert!(!state.dirty);
+        type_keys(&mut state, &[Key::Char('x')]);
+        assert!(state.dirty);
+        assert!(state.handle_command(Command::Quit { forcefully: false }));
+
+        state.handle_command(Command::Save {
+            filename: Some(file.display().to_string()),
+        });
+        assert!(!state.dirty);
+        assert_eq!(state.save_file.as_ref(), Some(&file));
+
+        type_keys(&mut state, &[Key::Char('x')]);
+        state.handle_command(Command::Save {
+            filename: Some(copy.display().to_string()),
+        });
+        assert!(state.dirty);
+        assert_eq!(state.save_file.as_ref(), Some(&file));
+        assert!(!state.handle_command(Command::SaveAndQuit {
+            filename: Some(copy.display().to_string()),
+        }));
+        // A failed write does not quit
+        assert!(state.handle_command(Command::SaveAndQuit {
+            filename: Some(dir.display().to_string()),
+        }));
+        assert!(!state.handle_command(Command::Quit { forcefully: true }));
+
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(copy);
+    }
+
+    #[test]
+    fn save_end_of_line() {
+        let file = std::env::temp_dir().join(format!("vim-rs-eol-save-{}", std::process::id()));
+        let edit_and_save = |state: &mut State| {
+            state.handle_command(Command::Edit {
+                filename: Some(file.display().to_string()),
+                forcefully: true,
+            });
+            state.handle_command(Command::Save { filename: None });
+            std::fs::read_to_string(&file).unwrap()
+        };
+
+        let mut state = state_with_lines(&[]);
+        std::fs::write(&file, "no\nnewline").unwrap();
+        state.config.fix_end_of_line = false;
+        assert_eq!(edit_and_save(&mut state), "no\nnewline");
+        std::fs::write(&file, "one\n").unwrap();
+        assert_eq!(edit_and_save(&mut state), "one\n");
+
+        std::fs::write(&file, "no\nnewline").unwrap();
+        state.config.fix_end_of_line = true;
+        assert_eq!(edit_and_save(&mut state), "no\nnewline\n");
+
+        // Files are written back as they were read
+        state.config.fix_end_of_line = false;
+        for text in ["one\ntwo\n", "one\ntwo", "\n", ""] {
+            std::fs::write(&file, text).unwrap();
+            assert_eq!(edit_and_save(&mut state), text);
+            assert!(
+                state
+                    .message
+                    .msg
+                    .ends_with(&format!("{}B written", text.len()))
+            );
+        }
+        state.config.fix_end_of_line = true;
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(edit_and_save(&mut state), "");
+
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn trim_on_save() {
+        let file = std::env::temp_dir().join(format!("vim-rs-trim-{}", std::process::id()));
+        let save = || Command::Save {
+            filename: Some(file.display().to_string()),
+        };
+
+        let mut state = state_with_lines(&["keep  ", "\t ", "tabs\t\t"]);
+        state.handle_command(save());
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            "keep  \n\t \ntabs\t\t\n"
+        );
+
+        state.config.trim_trailing_whitespace = true;
+        type_keys(&mut state, &[Key::Char('$')]);
+        state.handle_command(save());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep\n\ntabs\n");
+        assert_eq!(lines(&state), ["keep", "", "tabs"]);
+        // The cursor was on a trimmed space
+        assert_eq!(state.cursor_pos.col, 3);
+        assert!(!state.dirty);
+
+        // Trimming is undone at once
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["keep  ", "\t ", "tabs\t\t"]);
+
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn quit_keys() {
+        let mut state = state_with_lines(&["text"]);
+        assert!(state.handle_keypress(&Key::Char('Z')));
+        assert!(!state.handle_keypress(&Key::Char('Z')));
+
+        // ZZ cannot write a buffer without a file name
+        type_keys(&mut state, &[Key::Char('x')]);
+        assert!(state.handle_keypress(&Key::Char('Z')));
+        assert!(state.handle_keypress(&Key::Char('Z')));
+        assert_eq!(state.message.msg, "No file name");
+
+        assert!(state.handle_keypress(&Key::Char('Z')));
+        assert!(!state.handle_keypress(&Key::Char('Q')));
+
+        // Anything else cancels
+        type_keys(&mut state, &[Key::Char('Z'), Key::Escape]);
+        assert_eq!(state.pending, None);
+    }
 }
@@ -0,0 +1,200 @@
+use crate::theme::{CursorShape, Theme};
+
+#[derive(Debug)]
+pub struct Config {
+    /// Number of columns between two tab stops
+    pub tab_width: usize,
+    /// Insert spaces instead of a tab character when pressing Tab
+    pub expand_tab: bool,
+    /// Display line numbers in front of each line
+    pub show_line_numbers: bool,
+    /// Display the distance to the cursor line instead, combined with
+    /// `show_line_numbers` the cursor line shows its absolute number
+    pub relative_line_numbers: bool,
+    /// New lines start with the indentation of the line they come from
+    pub auto_indent: bool,
+    /// Show tabs, spaces and line ends with visible characters
+    pub list: bool,
+    /// Lines wider than the window continue on the next screen rows
+    pub wrap: bool,
+    /// Color the text when the language of the file is known
+    pub syntax: bool,
+    /// Spaces and tabs at the end of lines are removed when writing the file
+    pub trim_trailing_whitespace: bool,
+    /// Files are written with a newline after the last line, even if they were read
+    /// without one
+    pub fix_end_of_line: bool,
+    /// Search and substitute patterns are regular expressions instead of literal text
+    pub regex: bool,
+    /// Searches match letters whatever their case
+    pub ignore_case: bool,
+    /// With `ignore_case`, a search with an uppercase letter is case sensitive again
+    pub smart_case: bool,
+    /// Cursor outside of insertion mode
+    pub normal_cursor: CursorShape,
+    pub insert_cursor: CursorShape,
+    /// Colors of the UI
+    pub theme: Theme,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_width: 4,
+            expand_tab: true,
+            show_line_numbers: true,
+            relative_line_numbers: false,
+            auto_indent: false,
+            list: false,
+            wrap: false,
+            syntax: true,
+            trim_trailing_whitespace: false,
+            fix_end_of_line: true,
+            regex: true,
+            ignore_case: false,
+            smart_case: false,
+            normal_cursor: CursorShape::SteadyBlock,
+            insert_cursor: CursorShape::SteadyBar,
+            theme: Theme::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OptionError {
+    Unknown(String),
+    InvalidArgument(String),
+}
+
+impl Config {
+    /// Applies an argument of `:set`, like `number`, `nonumber`, `tabstop=8` or `insertcursor=bar`
+    pub fn set(&mut self, option: &str) -> Result<(), OptionError> {
+        if let Some((name, value)) = option.split_once('=') {
+            return match name {
+                "tabstop" | "ts" => match value.parse() {
+                    Ok(width) if width > 0 => {
+                        self.tab_width = width;
+                        Ok(())
+                    }
+                    _ => Err(OptionError::InvalidArgument(option.to_owned())),
+                },
+                "normalcursor" | "insertcursor" => {
+                    let shape = CursorShape::by_name(value)
+                        .ok_or_else(|| OptionError::InvalidArgument(option.to_owned()))?;
+                    if name == "normalcursor" {
+                        self.normal_cursor = shape;
+                    } else {
+                        self.insert_cursor = shape;
+                    }
+                    Ok(())
+                }
+                _ => Err(OptionError::Unknown(name.to_owned())),
+            };
+        }
+
+        let (name, enabled) = match option.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (option, true),
+        };
+        let flag = match name {
+            "number" | "nu" => &mut self.show_line_numbers,
+            "relativenumber" | "rnu" => &mut self.relative_line_numbers,
+            "expandtab" | "et" => &mut self.expand_tab,
+            "autoindent" | "ai" => &mut self.auto_indent,
+            "list" => &mut self.list,
+            "wrap" => &mut self.wrap,
+            "syntax" | "syn" => &mut self.syntax,
+            "trimtrailingwhitespace" | "ttw" => &mut self.trim_trailing_whitespace,
+            "fixendofline" | "fixeol" => &mut self.fix_end_of_line,
+            "regex" | "re" => &mut self.regex,
+            "ignorecase" | "ic" => &mut self.ignore_case,
+            "smartcase" | "scs" => &mut self.smart_case,
+            "tabstop" | "ts" | "normalcursor" | "insertcursor" => {
+                return Err(OptionError::InvalidArgument(option.to_owned()));
+            }
+            _ => return Err(OptionError::Unknown(option.to_owned())),
+        };
+        *flag = enabled;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{Config, OptionError},
+        theme::CursorShape,
+    };
+
+    #[test]
+    fn set_options() {
+        let mut config = Config::default();
+        config.set("nonumber").unwrap();
+        assert!(!config.show_line_numbers);
+        config.set("nu").unwrap();
+        assert!(config.show_line_numbers);
+
+        config.set("relativenumber").unwrap();
+        assert!(config.relative_line_numbers);
+        config.set("noet").unwrap();
+        assert!(!config.expand_tab);
+        config.set("ai").unwrap();
+        assert!(config.auto_indent);
+        config.set("list").unwrap();
+        assert!(config.list);
+        config.set("nolist").unwrap();
+        assert!(!config.list);
+        config.set("wrap").unwrap();
+        assert!(config.wrap);
+        config.set("nosyntax").unwrap();
+        assert!(!config.syntax);
+        config.set("ic").unwrap();
+        config.set("smartcase").unwrap();
+        assert!(config.ignore_case && config.smart_case);
+        config.set("noregex").unwrap();
+        assert!(!config.regex);
+        config.set("ttw").unwrap();
+        assert!(config.trim_trailing_whitespace);
+        config.set("nofixeol").unwrap();
+        assert!(!config.fix_end_of_line);
+        assert!(!config.regex);
+
+        config.set("tabstop=8").unwrap();
+        assert_eq!(config.tab_width, 8);
+        config.set("ts=2").unwrap();
+        assert_eq!(config.tab_width, 2);
+
+        config.set("insertcursor=blinkbar").unwrap();
+        assert_eq!(config.insert_cursor, CursorShape::BlinkingBar);
+        config.set("normalcursor=underline").unwrap();
+        assert_eq!(config.normal_cursor, CursorShape::SteadyUnderline);
+    }
+
+    #[test]
+    fn set_invalid_options() {
+        let mut config = Config::default();
+        assert!(matches!(config.set("foo"), Err(OptionError::Unknown(name)) if name == "foo"));
+        assert!(matches!(config.set("nofoo"), Err(OptionError::Unknown(name)) if name == "nofoo"));
+        assert!(matches!(config.set("foo=1"), Err(OptionError::Unknown(name)) if name == "foo"));
+        assert!(matches!(
+            config.set("number=1"),
+            Err(OptionError::Unknown(name)) if name == "number"
+        ));
+
+        for option in [
+            "tabstop=0",
+            "tabstop=abc",
+            "tabstop",
+            "notabstop",
+            "insertcursor=square",
+            "insertcursor",
+        ] {
+            assert!(matches!(
+                config.set(option),
+                Err(OptionError::InvalidArgument(_))
+            ));
+        }
+        assert_eq!(config.tab_width, 4);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{State, line::Line};
+
+/// Position in the buffer as (line index, codepoint column)
+pub type Position = (usize, usize);
+
+impl State {
+    /// Returns the text from `start` to `end` included, lines are separated by `\n`
+    pub fn text_in_range(&self, start: Position, end: Position) -> String {
+        let mut text = String::new();
+        for index in start.0..=end.0 {
+            let line = &self.text_lines[index];
+            let from = if index == start.0 { start.1 } else { 0 };
+            let to = if index == end.0 {
+                end.1 + 1
+            } else {
+                line.len()
+            };
+
+            text.extend(line.chars().take(to).skip(from));
+            if index != end.0 {
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    /// Removes the text from `start` to `end` included and returns it
+    pub fn delete_range(&mut self, start: Position, end: Position) -> String {
+        let removed = self.text_in_range(start, end);
+
+        let mut joined: String = self.text_lines[start.0].chars().take(start.1).collect();
+        joined.extend(self.text_lines[end.0].chars().skip(end.1 + 1));
+
+        self.text_lines.drain(start.0 + 1..=end.0);
+        self.adjust_marks(start.0 + 1, end.0 - start.0, 0);
+        self.text_lines[start.0] = Line::with_string(joined);
+        self.dirty = true;
+
+        removed
+    }
+
+    /// Inserts `text` at `pos`, `\n` characters split the line
+    /// Returns the position right after the inserted text
+    pub fn insert_text(&mut self, pos: Position, text: &str) -> Position {
+        if !text.contains('\n') {
+            let Some(col) = self.text_lines[pos.0].insert_str(pos.1, text) else {
+                return pos;
+            };
+            self.dirty = true;
+
+            return (pos.0, col);
+        }
+
+        let tail = self.text_lines[pos.0].split_off(pos.1);
+
+        let mut pieces = text.split('\n');
+        self.text_lines[pos.0].extend(pieces.next().unwrap_or_default().chars());
+
+        let mut new_lines: Vec<Line> = pieces
+            .map(|piece| Line::with_string(piece.to_owned()))
+            .collect();
+        let last = new_lines.last_mut().expect("text contains a newline");
+        let end_col = last.len();
+        last.extend(tail.chars());
+
+        let end_row = pos.0 + new_lines.len();
+        self.adjust_marks(pos.0 + 1, 0, new_lines.len());
+        self.text_lines.splice(pos.0 + 1..pos.0 + 1, new_lines);
+        self.dirty = true;
+
+        (end_row, end_col)
+    }
+
+    /// Removes the spaces and tabs at the end of every line in one undo step
+    /// Returns true if any line changed
+    pub fn trim_trailing_whitespace(&mut self) -> bool {
+        let Some(first) = self
+            .text_lines
+            .iter()
+            .position(|line| line.as_str().ends_with([' ', '\t']))
+        else {
+            return false;
+        };
+
+        self.save_undo_point();
+        for line in &mut self.text_lines[first..] {
+            line.trim_trailing_whitespace();
+        }
+        self.dirty = true;
+        // The cursor may have been on removed whitespace
+        let len = self.get_current_line().map_or(0, Line::len);
+        self.cursor_pos.col = self.cursor_pos.col.min(len.saturating_sub(1));
+        self.target_col = self.target_col.min(self.cursor_pos.col);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{lines, state_with_lines};
+
+    #[test]
+    fn text_in_range() {
+        let state = state_with_lines(&["hello", "wörld", "foo"]);
+        assert_eq!(state.text_in_range((0, 1), (0, 3)), "ell");
+        assert_eq!(state.text_in_range((0, 3), (2, 0)), "lo\nwörld\nf");
+        assert_eq!(state.text_in_range((1, 3), (2, 10)), "ld\nfoo");
+    }
+
+    #[test]
+    fn delete_range() {
+        let mut state = state_with_lines(&["hello", "wörld", "foo"]);
+        assert_eq!(state.delete_range((0, 1), (0, 3)), "ell");
+        assert_eq!(lines(&state), ["ho", "wörld", "foo"]);
+
+        assert_eq!(state.delete_range((0, 1), (2, 1)), "o\nwörld\nfo");
+        assert_eq!(lines(&state), ["ho"]);
+    }
+
+    #[test]
+    fn insert_text() {
+        let mut state = state_with_lines(&["hello", "world"]);
+        assert_eq!(state.insert_text((0, 2), "yy"), (0, 4));
+        assert_eq!(lines(&state), ["heyyllo", "world"]);
+
+        assert_eq!(state.insert_text((1, 1), "1\n2\n3"), (3, 1));
+        assert_eq!(lines(&state), ["heyyllo", "w1", "2", "3orld"]);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::line::{CharClass, Line, char_width, display_width};
+
+/// Characters of the line being edited with a gap at the cursor,
+/// typing and deleting around the cursor never moves the rest of the line
+#[derive(Debug)]
+pub struct GapBuffer {
+    chars: Vec<char>,
+    gap_start: usize,
+    gap_end: usize,
+    /// Display width of the text before the cursor, kept up to date so that
+    /// drawing the cursor does not scan the line
+    before_width: usize,
+    tab_width: usize,
+}
+
+impl GapBuffer {
+    /// Size of the gap when the buffer is created or grown
+    const GAP_SIZE: usize = 64;
+
+    /// Creates a buffer holding `line` with the gap at the codepoint `cursor`
+    /// `tab_width` is used to compute the display width before the cursor
+    #[must_use]
+    pub fn new(line: &Line, cursor: usize, tab_width: usize) -> Self {
+        let cursor = cursor.min(line.len());
+        let mut chars = Vec::with_capacity(line.len() + Self::GAP_SIZE);
+        chars.extend(line.chars().take(cursor));
+        chars.resize(cursor + Self::GAP_SIZE, '\0');
+        chars.extend(line.chars().skip(cursor));
+
+        GapBuffer {
+            chars,
+            gap_start: cursor,
+            gap_end: cursor + Self::GAP_SIZE,
+            before_width: line.get_unicode_width_at(cursor, tab_width),
+            tab_width,
+        }
+    }
+
+    /// Codepoint index of the cursor
+    #[must_use]
+    pub const fn cursor(&self) -> usize {
+        self.gap_start
+    }
+
+    /// Display column of the cursor
+    #[must_use]
+    pub const fn cursor_width(&self) -> usize {
+        self.before_width
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.chars.len() - (self.gap_end - self.gap_start)
+    }
+
+    /// Characters before the cursor
+    #[must_use]
+    pub fn before(&self) -> &[char] {
+        &self.chars[..self.gap_start]
+    }
+
+    /// Characters after the cursor
+    #[must_use]
+    pub fn after(&self) -> &[char] {
+        &self.chars[self.gap_end..]
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.before().iter().chain(self.after()).copied()
+    }
+
+    pub fn insert(&mut self, c: char) {
+        if self.gap_start == self.gap_end {
+            self.grow();
+        }
+        self.chars[self.gap_start] = c;
+        self.gap_start += 1;
+        self.before_width += char_width(c, self.before_width, self.tab_width);
+    }
+
+    /// Removes the character before the cursor
+    pub fn delete_before(&mut self) -> Option<char> {
+        if self.gap_start == 0 {
+            return None;
+        }
+        self.retreat_to(self.gap_start - 1);
+        Some(self.chars[self.gap_start])
+    }
+
+    /// Removes the character after the cursor
+    pub fn delete_after(&mut self) -> Option<char> {
+        if self.gap_end == self.chars.len() {
+            return None;
+        }
+        self.gap_end += 1;
+        Some(self.chars[self.gap_end - 1])
+    }
+
+    /// Removes the blanks and the word before the cursor, words are split like for `b`
+    /// Returns the number of characters removed
+    pub fn delete_word_before(&mut self) -> usize {
+        let before = self.before();
+        let blanks = before
+            .iter()
+            .rev()
+            .take_while(|&&c| CharClass::of(c) == CharClass::Blank)
+            .count();
+        let rest = &before[..before.len() - blanks];
+        let word = rest.last().map_or(0, |&last| {
+            let class = CharClass::of(last);
+            rest.iter()
+                .rev()
+                .take_while(|&&c| CharClass::of(c) == class)
+                .count()
+        });
+
+        self.retreat_to(self.gap_start - blanks - word);
+        blanks + word
+    }
+
+    /// Removes the text before the cursor but keeps the indentation,
+    /// which goes too when there is nothing else before the cursor
+    /// Returns the number of characters removed
+    pub fn delete_to_line_start(&mut self) -> usize {
+        let indent = self
+            .before()
+            .iter()
+            .take_while(|&&c| c == ' ' || c == '\t')
+            .count();
+        let start = if indent < self.gap_start { indent } else { 0 };
+
+        let removed = self.gap_start - start;
+        self.retreat_to(start);
+        removed
+    }
+
+    /// Moves the cursor to the codepoint `index`, clamped to the length of the text
+    pub fn move_to(&mut self, index: usize) {
+        let index = index.min(self.len());
+        if index < self.gap_start {
+            let moved = self.gap_start - index;
+            self.before_width = self.width_before(index);
+            self.chars
+                .copy_within(index..self.gap_start, self.gap_end - moved);
+            self.gap_start = index;
+            self.gap_end -= moved;
+        } else if index > self.gap_start {
+            let moved = index - self.gap_start;
+            self.chars
+                .copy_within(self.gap_end..self.gap_end + moved, self.gap_start);
+            for &c in &self.chars[self.gap_start..index] {
+                self.before_width += char_width(c, self.before_width, self.tab_width);
+            }
+            self.gap_start += moved;
+            self.gap_end += moved;
+        }
+    }
+
+    /// Moves the text before the cursor into `line`, replacing its content
+    pub fn split_before_into(&mut self, line: &mut Line) {
+        line.clear();
+        line.extend(self.before().iter().copied());
+        self.gap_start = 0;
+        self.before_width = 0;
+    }
+
+    /// Builds the line without the gap
+    #[must_use]
+    pub fn to_line(&self) -> Line {
+        Line::with_string(self.chars().collect())
+    }
+
+    /// Drops the characters between `index` and the cursor, which join the gap
+    fn retreat_to(&mut self, index: usize) {
+        self.before_width = self.width_before(index);
+        self.gap_start = index;
+    }
+
+    /// Display width of the characters before `index`, which is at most the cursor
+    /// Only tabs depend on the column they are drawn at, so the width of the
+    /// other characters can simply be taken off
+    fn width_before(&self, index: usize) -> usize {
+        let dropped = &self.chars[index..self.gap_start];
+        if dropped.contains(&'\t') {
+            return display_width(self.chars[..index].iter().copied(), self.tab_width);
+        }
+
+        self.before_width
+            - dropped
+                .iter()
+                .map(|&c| char_width(c, 0, self.tab_width))
+                .sum::<usize>()
+    }
+
+    fn grow(&mut self) {
+        let after = self.chars.len() - self.gap_end;
+        self.chars
+            .resize(self.chars.len() + Self::GAP_SIZE.max(self.len() / 2), '\0');
+        let new_end = self.chars.len() - after;
+        self.chars
+            .copy_within(self.gap_end..self.gap_end + after, new_end);
+        self.gap_end = new_end;
+    }
+}
+
+impl Extend<char> for GapBuffer {
+    fn extend<T: IntoIterator<Item = char>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|c| self.insert(c));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gap_buffer::GapBuffer, line::Line};
+
+    fn text(buffer: &GapBuffer) -> String {
+        buffer.chars().collect()
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut buffer = GapBuffer::new(&Line::with_string("hllo".to_owned()), 1, 4);
+        buffer.insert('e');
+        assert_eq!(text(&buffer), "hello");
+        assert_eq!(buffer.cursor(), 2);
+
+        assert_eq!(buffer.delete_after(), Some('l'));
+        assert_eq!(buffer.delete_before(), Some('e'));
+        assert_eq!(text(&buffer), "hlo");
+        assert_eq!(buffer.before(), ['h']);
+        assert_eq!(buffer.after(), ['l', 'o']);
+
+        buffer.move_to(0);
+        assert_eq!(buffer.delete_before(), None);
+        buffer.move_to(10);
+        assert_eq!(buffer.delete_after(), None);
+        assert_eq!(buffer.cursor(), 3);
+    }
+
+    #[test]
+    fn delete_word_before() {
+        let mut buffer = GapBuffer::new(&Line::from("foo.bar  baz  "), 14, 4);
+        assert_eq!(buffer.delete_word_before(), 5);
+        assert_eq!(text(&buffer), "foo.bar  ");
+        assert_eq!(buffer.delete_word_before(), 5);
+        assert_eq!(buffer.delete_word_before(), 1);
+        assert_eq!(buffer.delete_word_before(), 3);
+        assert_eq!(buffer.delete_word_before(), 0);
+        assert_eq!(text(&buffer), "");
+
+        let mut buffer = GapBuffer::new(&Line::from("été après"), 6, 4);
+        assert_eq!(buffer.delete_word_before(), 2);
+        assert_eq!(text(&buffer), "été rès");
+    }
+
+    #[test]
+    fn delete_to_line_start() {
+        let mut buffer = GapBuffer::new(&Line::from("\t  foo bar"), 9, 4);
+        assert_eq!(buffer.delete_to_line_start(), 6);
+        assert_eq!(text(&buffer), "\t  r");
+        assert_eq!(buffer.delete_to_line_start(), 3);
+        assert_eq!(buffer.delete_to_line_start(), 0);
+        assert_eq!(text(&buffer), "r");
+    }
+
+    #[test]
+    fn cursor_width() {
+        let mut buffer = GapBuffer::new(&Line::from("a\t日b"), 3, 4);
+        assert_eq!(buffer.cursor_width(), 6);
+
+        buffer.insert('本');
+        assert_eq!(buffer.cursor_width(), 8);
+        buffer.delete_before();
+        buffer.delete_before();
+        assert_eq!(buffer.cursor_width(), 4);
+        // The tab shrinks when the text before it gets shorter
+        buffer.move_to(1);
+        buffer.delete_before();
+        assert_eq!(buffer.cursor_width(), 0);
+        buffer.move_to(1);
+        assert_eq!(buffer.cursor_width(), 4);
+
+        buffer.move_to(buffer.len());
+        assert_eq!(buffer.cursor_width(), 5);
+        buffer.extend("foo bar".chars());
+        assert_eq!(buffer.delete_word_before(), 3);
+        assert_eq!(buffer.cursor_width(), 9);
+        assert_eq!(buffer.delete_to_line_start(), 5);
+        assert_eq!(buffer.cursor_width(), 4);
+        assert_eq!(text(&buffer), "\t");
+    }
+
+    #[test]
+    fn grow() {
+        let mut buffer = GapBuffer::new(&Line::with_string("日本".to_owned()), 1, 4);
+        buffer.extend(std::iter::repeat_n('a', 1000));
+        assert_eq!(buffer.len(), 1002);
+        assert_eq!(buffer.after(), ['本']);
+
+        buffer.move_to(0);
+        buffer.insert('>');
+        let line = buffer.to_line();
+        assert!(line.as_str().starts_with(">日aaa"));
+        assert!(line.as_str().ends_with("aaa本"));
+        assert_eq!(line.len(), 1003);
+    }
+
+    #[test]
+    fn split_before_into() {
+        let mut line = Line::with_string("first second".to_owned());
+        let mut buffer = GapBuffer::new(&line, 6, 4);
+        buffer.split_before_into(&mut line);
+        assert_eq!(line.as_str(), "first ");
+        assert_eq!(buffer.cursor(), 0);
+        assert_eq!(text(&buffer), "second");
+    }
+}
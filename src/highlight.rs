@@ -0,0 +1,153 @@
+use std::{fmt::Debug, path::Path};
+
+use crate::line::{CharClass, Line};
+
+/// Kinds of text that get their own color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Number,
+    String,
+    Keyword,
+}
+
+/// Characters `start..end` of a line, as codepoint indices
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: TokenKind,
+}
+
+/// Splits lines into colored spans, each language can provide its own
+pub trait Highlighter: Debug {
+    /// Returns sorted spans that do not overlap, text outside of them is not colored
+    fn highlight(&self, line: &Line) -> Vec<Span>;
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+/// Returns the highlighter for the language of the file at `path`, if one is known
+#[must_use]
+pub fn for_path(path: &Path) -> Option<Box<dyn Highlighter>> {
+    match path.extension()?.to_str()? {
+        "rs" => Some(Box::new(KeywordHighlighter::new(RUST_KEYWORDS))),
+        _ => None,
+    }
+}
+
+/// Colors numbers, double-quoted strings and a list of keywords
+#[derive(Debug)]
+pub struct KeywordHighlighter {
+    keywords: Vec<String>,
+}
+
+impl KeywordHighlighter {
+    #[must_use]
+    pub fn new(keywords: &[&str]) -> Self {
+        KeywordHighlighter {
+            keywords: keywords.iter().map(|&k| k.to_owned()).collect(),
+        }
+    }
+}
+
+impl Highlighter for KeywordHighlighter {
+    fn highlight(&self, line: &Line) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut chars = line.char_enumerate().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c == '"' {
+                // An unterminated string goes to the end of the line
+                let mut end = line.len();
+                let mut escaped = false;
+                for (i, c) in chars.by_ref() {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                spans.push(Span {
+                    start,
+                    end,
+                    kind: TokenKind::String,
+                });
+            } else if CharClass::of(c) == CharClass::Word {
+                let mut word = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|&(_, c)| CharClass::of(c) == CharClass::Word)
+                {
+                    word.push(c);
+                }
+
+                let kind = if c.is_ascii_digit() {
+                    TokenKind::Number
+                } else if self.keywords.contains(&word) {
+                    TokenKind::Keyword
+                } else {
+                    continue;
+                };
+                spans.push(Span {
+                    start,
+                    end: start + word.chars().count(),
+                    kind,
+                });
+            }
+        }
+
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        highlight::{Highlighter, KeywordHighlighter, Span, TokenKind, for_path},
+        line::Line,
+    };
+
+    fn spans(line: &str) -> Vec<(usize, usize, TokenKind)> {
+        KeywordHighlighter::new(&["let", "fn"])
+            .highlight(&Line::from(line))
+            .into_iter()
+            .map(|Span { start, end, kind }| (start, end, kind))
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            spans("let x = 42;"),
+            [(0, 3, TokenKind::Keyword), (8, 10, TokenKind::Number)]
+        );
+        // Keywords and numbers inside other words are not colored
+        assert_eq!(spans("letter x2 0x1f"), [(10, 14, TokenKind::Number)]);
+        assert_eq!(spans("fn été()"), [(0, 2, TokenKind::Keyword)]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            spans(r#"f("日本 \"fn\"", 1)"#),
+            [(2, 13, TokenKind::String), (15, 16, TokenKind::Number)]
+        );
+        assert_eq!(spans(r#"a "open"#), [(2, 7, TokenKind::String)]);
+        assert_eq!(spans(""), []);
+    }
+
+    #[test]
+    fn languages() {
+        assert!(for_path(Path::new("src/main.rs")).is_some());
+        assert!(for_path(Path::new("notes.txt")).is_none());
+        assert!(for_path(Path::new("Makefile")).is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{Message, MessageType, State, line::Line};
+
+/// State of the buffer before a change
+#[derive(Debug)]
+pub struct Snapshot {
+    lines: Vec<Line>,
+    line_index: usize,
+    col: usize,
+}
+
+#[derive(Debug)]
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// Number of changes pushed so far, tells whether a command modified the buffer
+    changes: usize,
+    /// Number of undo steps of the buffer as it was loaded or last written, None once
+    /// that state was undone and then replaced by another change
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            changes: 0,
+            saved: Some(0),
+        }
+    }
+}
+
+impl History {
+    /// Records a new change, which invalidates the changes that were undone
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        self.undo.push(snapshot);
+        self.redo.clear();
+        self.changes += 1;
+    }
+
+    /// Remembers the current state as the one in the file
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    /// Undo and redo are back to the state in the file
+    fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    pub const fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Drops the last snapshot if the buffer is identical to it
+    /// This avoids empty undo steps, e.g. after entering and leaving insertion mode
+    pub fn discard_if_unchanged(&mut self, lines: &[Line]) {
+        if let Some(last) = self.undo.last()
+            && last.lines == lines
+        {
+            self.undo.pop();
+        }
+    }
+}
+
+impl State {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            lines: self.text_lines.clone(),
+            line_index: self.current_line_index(),
+            col: self.cursor_pos.col,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.text_lines = snapshot.lines;
+        self.move_to_line(snapshot.line_index);
+        self.target_col = snapshot.col;
+        self.clamp_col_to_current_line();
+        self.dirty = !self.history.is_saved();
+    }
+
+    /// Must be called before modifying the buffer so the change can be undone
+    pub fn save_undo_point(&mut self) {
+        let snapshot = self.snapshot();
+        self.history.push(snapshot);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(snapshot) = self.history.undo.pop() {
+            let current = self.snapshot();
+            self.history.redo.push(current);
+            self.restore(snapshot);
+        } else {
+            self.message = Message {
+                msg: "Already at oldest change".to_owned(),
+                r#type: MessageType::Info,
+            };
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(snapshot) = self.history.redo.pop() {
+            let current = self.snapshot();
+            self.history.undo.push(current);
+            self.restore(snapshot);
+        } else {
+            self.message = Message {
+                msg: "Already at newest change".to_owned(),
+                r#type: MessageType::Info,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        command_parser::Command,
+        key::Key,
+        test_utils::{keys, lines, state_with_lines, type_keys},
+    };
+    #[test]
+    fn insert_undo_redo() {
+        let mut state = state_with_lines(&["hello"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('A'), Key::Char('!'), Key::Char('!'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["hello!!"]);
+        assert_eq!(state.cursor_pos.col, 7);
+
+        // The restored column is clamped to the last character
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["hello"]);
+        assert_eq!(state.cursor_pos.col, 4);
+
+        type_keys(&mut state, &[Key::Ctrl('r')]);
+        assert_eq!(lines(&state), ["hello!!"]);
+        assert_eq!(state.cursor_pos.col, 6);
+
+        // Nothing left to redo
+        type_keys(&mut state, &[Key::Ctrl('r')]);
+        assert_eq!(lines(&state), ["hello!!"]);
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut state = state_with_lines(&["abc"]);
+        type_keys(&mut state, &[Key::Char('x'), Key::Char('u')]);
+        assert_eq!(lines(&state), ["abc"]);
+
+        type_keys(
+            &mut state,
+            &[Key::Char('$'), Key::Char('x'), Key::Ctrl('r')],
+        );
+        assert_eq!(lines(&state), ["ab"]);
+    }
+
+    #[test]
+    fn empty_insertion_is_not_an_undo_step() {
+        let mut state = state_with_lines(&["abc"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('x'), Key::Char('i'), Key::Escape, Key::Char('u')],
+        );
+        assert_eq!(lines(&state), ["abc"]);
+
+        // Undoing past the oldest change does nothing
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["abc"]);
+    }
+
+    #[test]
+    fn undo_to_saved_state() {
+        let file = std::env::temp_dir().join(format!("vim-rs-undo-saved-{}", std::process::id()));
+        let mut state = state_with_lines(&["abc"]);
+        type_keys(&mut state, &keys("xu"));
+        assert!(!state.dirty);
+        type_keys(&mut state, &[Key::Ctrl('r')]);
+        assert!(state.dirty);
+
+        state.handle_command(Command::Save {
+            filename: Some(file.display().to_string()),
+        });
+        type_keys(&mut state, &keys("u"));
+        assert!(state.dirty);
+        type_keys(&mut state, &[Key::Ctrl('r')]);
+        assert!(!state.dirty);
+
+        // The written state cannot come back once another change replaced it
+        type_keys(&mut state, &keys("ux"));
+        type_keys(&mut state, &keys("u"));
+        assert_eq!(lines(&state), ["abc"]);
+        assert!(state.dirty);
+
+        let _ = std::fs::remove_file(file);
+    }
+}
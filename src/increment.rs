@@ -0,0 +1,148 @@
+use crate::{State, line::Line};
+
+impl State {
+    /// Adds `delta` to the decimal number under or after the cursor on its line, like
+    /// `Ctrl-A` and `Ctrl-X`, a `-` right before the digits makes it negative
+    /// Leading zeros keep the number of digits, the cursor ends on the last one
+    /// Returns false if there is no number
+    pub fn add_to_number(&mut self, delta: i128) -> bool {
+        let index = self.current_line_index();
+        let chars: Vec<char> = self.text_lines[index].chars().collect();
+        let col = self.cursor_pos.col.min(chars.len());
+
+        // The number under the cursor starts before it
+        let mut start = col;
+        while start > 0
+            && chars[start - 1].is_ascii_digit()
+            && chars.get(start).is_some_and(char::is_ascii_digit)
+        {
+            start -= 1;
+        }
+        let Some(start) = (start..chars.len()).find(|&i| chars[i].is_ascii_digit()) else {
+            return false;
+        };
+        let end = (start..chars.len())
+            .find(|&i| !chars[i].is_ascii_digit())
+            .unwrap_or(chars.len());
+        let negative = start > 0 && chars[start - 1] == '-';
+
+        let digits: String = chars[start..end].iter().collect();
+        let Some(value) = digits
+            .parse::<i128>()
+            .ok()
+            .map(|value| if negative { -value } else { value })
+            .and_then(|value| value.checked_add(delta))
+        else {
+            return false;
+        };
+
+        let padding = if digits.starts_with('0') {
+            digits.len()
+        } else {
+            0
+        };
+        let sign = if value < 0 { "-" } else { "" };
+        let number = format!("{sign}{:0padding$}", value.unsigned_abs());
+
+        let from = start - usize::from(negative);
+        let mut text: String = chars[..from].iter().collect();
+        text.push_str(&number);
+        text.extend(&chars[end..]);
+
+        self.save_undo_point();
+        self.text_lines[index] = Line::with_string(text);
+        self.dirty = true;
+        self.cursor_pos.col = from + number.len() - 1;
+        self.target_col = self.cursor_pos.col;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn increment() {
+        let mut state = state_with_lines(&["9", "x = 41;", "été 7"]);
+        type_keys(&mut state, &[Key::Ctrl('a')]);
+        assert_eq!(lines(&state)[0], "10");
+        assert_eq!(state.cursor_pos.col, 1);
+
+        // The number is found after the cursor
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('0'), Key::Ctrl('a')],
+        );
+        assert_eq!(lines(&state)[1], "x = 42;");
+        assert_eq!(state.cursor_pos.col, 5);
+        // Also from its last digit
+        type_keys(
+            &mut state,
+            &[Key::Char('1'), Key::Char('0'), Key::Ctrl('x')],
+        );
+        assert_eq!(lines(&state)[1], "x = 32;");
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('2'),
+                Key::Char('5'),
+                Key::Ctrl('a'),
+            ],
+        );
+        assert_eq!(lines(&state)[2], "été 32");
+        assert_eq!(state.cursor_pos.col, 5);
+
+        // There is no number after the cursor
+        type_keys(
+            &mut state,
+            &[Key::Char('k'), Key::Char('$'), Key::Ctrl('a')],
+        );
+        assert_eq!(lines(&state)[1], "x = 32;");
+
+        type_keys(&mut state, &[Key::Char('u'), Key::Char('u')]);
+        assert_eq!(lines(&state), ["10", "x = 42;", "été 7"]);
+    }
+
+    #[test]
+    fn negative_numbers() {
+        let mut state = state_with_lines(&["-1", "a-b 3"]);
+        type_keys(&mut state, &[Key::Ctrl('a')]);
+        assert_eq!(lines(&state)[0], "0");
+        type_keys(&mut state, &[Key::Ctrl('x'), Key::Ctrl('x')]);
+        assert_eq!(lines(&state)[0], "-2");
+        assert_eq!(state.cursor_pos.col, 1);
+        type_keys(
+            &mut state,
+            &[Key::Char('1'), Key::Char('2'), Key::Ctrl('a')],
+        );
+        assert_eq!(lines(&state)[0], "10");
+
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('5'), Key::Ctrl('x')],
+        );
+        assert_eq!(lines(&state)[1], "a-b -2");
+    }
+
+    #[test]
+    fn leading_zeros() {
+        let mut state = state_with_lines(&["007", "x099", "-001"]);
+        type_keys(&mut state, &[Key::Ctrl('a')]);
+        assert_eq!(lines(&state)[0], "008");
+        assert_eq!(state.cursor_pos.col, 2);
+
+        type_keys(&mut state, &[Key::Char('j'), Key::Ctrl('a')]);
+        assert_eq!(lines(&state)[1], "x100");
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('3'), Key::Ctrl('a')],
+        );
+        assert_eq!(lines(&state)[2], "002");
+    }
+}
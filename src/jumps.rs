@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+
+use crate::{State, edit::Position};
+
+/// Positions the cursor jumped from, walked with Ctrl-O and Ctrl-I
+#[derive(Debug, Default, Clone)]
+pub struct JumpList {
+    jumps: VecDeque<Position>,
+    /// Index of the position the cursor is at, `jumps.len()` when it is not in the list
+    current: usize,
+}
+
+impl JumpList {
+    /// Oldest jumps are forgotten past this
+    const MAX_JUMPS: usize = 100;
+
+    /// Records `pos` as the newest jump, dropping the jumps that were walked back over
+    fn push(&mut self, pos: Position) {
+        self.jumps.truncate(self.current);
+        // Like Vim, a line is only remembered once
+        self.jumps.retain(|&(line, _)| line != pos.0);
+        self.jumps.push_back(pos);
+        if self.jumps.len() > Self::MAX_JUMPS {
+            self.jumps.pop_front();
+        }
+        self.current = self.jumps.len();
+    }
+
+    /// `from` is where the cursor is, remembered when leaving the end of the list
+    fn back(&mut self, from: Position) -> Option<Position> {
+        if self.current == 0 {
+            return None;
+        }
+        if self.current == self.jumps.len() {
+            self.jumps.push_back(from);
+        }
+        self.current -= 1;
+
+        Some(self.jumps[self.current])
+    }
+
+    fn forward(&mut self) -> Option<Position> {
+        if self.current + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.current += 1;
+
+        Some(self.jumps[self.current])
+    }
+}
+
+impl State {
+    /// Must be called before a jump, like `G` or a search, so that Ctrl-O can come back
+    pub fn push_jump(&mut self) {
+        let pos = (self.current_line_index(), self.cursor_pos.col);
+        self.jumps.push(pos);
+    }
+
+    /// Goes back to the previous position in the jump list, or forward with `back` false
+    pub fn walk_jumps(&mut self, back: bool) {
+        let from = (self.current_line_index(), self.cursor_pos.col);
+        let jump = if back {
+            self.jumps.back(from)
+        } else {
+            self.jumps.forward()
+        };
+
+        // Edits can leave positions past the end of the buffer
+        if let Some((line, col)) = jump {
+            self.move_to_line(line);
+            self.target_col = col;
+            self.clamp_col_to_current_line();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{state_with_lines, type_keys},
+    };
+
+    fn position(state: &crate::State) -> (usize, usize) {
+        (state.current_line_index(), state.cursor_pos.col)
+    }
+
+    #[test]
+    fn walk_jumps() {
+        let mut state = state_with_lines(&["one", "two", "three", "four"]);
+        type_keys(&mut state, &[Key::Char('l'), Key::Char('G')]);
+        type_keys(
+            &mut state,
+            &[Key::Char('k'), Key::Char('g'), Key::Char('g')],
+        );
+        assert_eq!(position(&state), (0, 0));
+
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (2, 0));
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (0, 1));
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (0, 1));
+
+        type_keys(&mut state, &[Key::Tab]);
+        assert_eq!(position(&state), (2, 0));
+        type_keys(&mut state, &[Key::Tab]);
+        assert_eq!(position(&state), (0, 0));
+        type_keys(&mut state, &[Key::Tab]);
+        assert_eq!(position(&state), (0, 0));
+    }
+
+    #[test]
+    fn jumps_are_clamped() {
+        let mut state = state_with_lines(&["one", "two", "three", "four"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('G'),
+                Key::Char('$'),
+                Key::Char('g'),
+                Key::Char('g'),
+            ],
+        );
+        type_keys(
+            &mut state,
+            &[Key::Char('4'), Key::Char('d'), Key::Char('d')],
+        );
+
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (0, 0));
+    }
+
+    #[test]
+    fn search_is_a_jump() {
+        let mut state = state_with_lines(&["one", "two", "three"]);
+        type_keys(&mut state, &[Key::Char('/'), Key::Char('h'), Key::Enter]);
+        assert_eq!(position(&state), (2, 1));
+
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (0, 0));
+    }
+}
@@ -1,27 +1,48 @@
-use std::{
-    error::Error,
-    fmt::Display,
-    io::{Read, StdinLock},
-};
+use std::{error::Error, fmt::Display, io::Read, time::Duration};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Key {
-    Char(u8),
+    Char(char),
     Escape,
     ArrowUp,
     ArrowDown,
     ArrowLeft,
     ArrowRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
     Delete,
     Backspace,
     Enter,
     Tab,
+    /// Ctrl combined with a lowercase letter
+    Ctrl(char),
+}
+
+/// How long to wait after Esc for the rest of an escape sequence
+/// Terminals send a whole sequence at once, so only a lone Esc keeps us waiting
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+/// Longest escape sequence read after Esc
+const MAX_SEQUENCE_LEN: usize = 7;
+
+/// Source of bytes that can tell whether more input is coming
+pub trait Input: Read {
+    /// Returns true if a byte can be read within `timeout`
+    fn poll(&mut self, timeout: Duration) -> bool;
+}
+
+impl Input for &[u8] {
+    fn poll(&mut self, _timeout: Duration) -> bool {
+        !self.is_empty()
+    }
 }
 
 #[derive(Debug)]
 pub enum SequenceParsingError {
     UnknownSequence(Vec<u8>),
     UnknownChar(u8),
+    InvalidUtf8(Vec<u8>),
     NoChar,
 }
 
@@ -36,50 +57,117 @@ impl Display for SequenceParsingError {
             SequenceParsingError::UnknownChar(c) => {
                 write!(f, "Received unknown character: {}", *c as char)
             }
+            SequenceParsingError::InvalidUtf8(bytes) => {
+                write!(f, "Received invalid UTF-8 sequence: {bytes:?}")
+            }
             SequenceParsingError::NoChar => write!(f, "Received no character",),
         }
     }
 }
 
-pub fn read_key(stdin: &mut StdinLock) -> Result<Key, SequenceParsingError> {
-    let mut buf = [0u8; 1];
-    if stdin.read(&mut buf).is_err() || buf[0] == 0 {
-        return Err(SequenceParsingError::NoChar);
-    }
-
-    match buf[0] {
-        b'\x08' | b'\x7f' => Ok(Key::Backspace),
-        b'\n' | b'\r' => Ok(Key::Enter),
-        b'\t' => Ok(Key::Tab),
-        b'\x1b' => {
-            // Read up to 7 more bytes non-blocking to consume the full sequence
-            let mut seq = [0u8; 7];
-            let n = match stdin.read(&mut seq) {
-                Ok(n) => n,
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(Key::Escape),
-                Err(_) => Err(SequenceParsingError::NoChar)?,
-            };
-
-            if n == 0 {
-                return Ok(Key::Escape);
+/// Reads keys from `input`
+/// A key typed right after Esc is read while looking for an escape sequence, it is kept
+/// for the next call
+pub struct KeyReader<I> {
+    input: I,
+    next: Option<u8>,
+}
+
+impl<I: Input> KeyReader<I> {
+    pub const fn new(input: I) -> Self {
+        KeyReader { input, next: None }
+    }
+
+    pub fn read_key(&mut self) -> Result<Key, SequenceParsingError> {
+        let first = match self.next.take() {
+            Some(byte) => byte,
+            None => {
+                let mut buf = [0u8; 1];
+                if self.input.read(&mut buf).is_err() || buf[0] == 0 {
+                    return Err(SequenceParsingError::NoChar);
+                }
+                buf[0]
             }
+        };
 
-            Ok(parse_escape_sequence(&seq[..n])?)
+        match first {
+            b'\x08' | b'\x7f' => Ok(Key::Backspace),
+            b'\n' | b'\r' => Ok(Key::Enter),
+            b'\t' => Ok(Key::Tab),
+            c @ 0x01..=0x1a => Ok(Key::Ctrl((b'a' + c - 1) as char)),
+            b'\x1b' => match read_escape_sequence(&mut self.input).as_slice() {
+                [] => Ok(Key::Escape),
+                // Not a sequence but a key typed quickly after Esc
+                &[byte] => {
+                    self.next = Some(byte);
+                    Ok(Key::Escape)
+                }
+                seq => parse_escape_sequence(seq),
+            },
+            c if c.is_ascii() => Ok(Key::Char(c as char)),
+            c => Ok(Key::Char(read_utf8_char(&mut self.input, c)?)),
         }
-        _ => {
-            if buf[0].is_ascii() || buf[0] == b' ' {
-                Ok(Key::Char(buf[0]))
-            } else {
-                Err(SequenceParsingError::UnknownChar(buf[0]))?
-            }
+    }
+}
+
+/// Reads the continuation bytes of a UTF-8 sequence starting with `first`
+fn read_utf8_char<R: Read>(stdin: &mut R, first: u8) -> Result<char, SequenceParsingError> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Err(SequenceParsingError::UnknownChar(first)),
+    };
+
+    let mut bytes = [first, 0, 0, 0];
+    let mut n = 1;
+    while n < len {
+        match stdin.read(&mut bytes[n..len]) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => n += read,
         }
     }
+
+    std::str::from_utf8(&bytes[..n])
+        .ok()
+        .and_then(|s| s.chars().next())
+        .ok_or_else(|| SequenceParsingError::InvalidUtf8(bytes[..n].to_owned()))
+}
+
+/// Reads what follows Esc, empty if nothing comes before the timeout
+/// A CSI sequence ends with a byte in `0x40..=0x7e` after the `[`, an SS3 one with the
+/// byte after the `O`
+fn read_escape_sequence<I: Input>(stdin: &mut I) -> Vec<u8> {
+    let mut seq = Vec::with_capacity(MAX_SEQUENCE_LEN);
+    let mut byte = [0u8; 1];
+    while seq.len() < MAX_SEQUENCE_LEN && stdin.poll(ESCAPE_TIMEOUT) {
+        if !matches!(stdin.read(&mut byte), Ok(1)) {
+            break;
+        }
+        seq.push(byte[0]);
+
+        let finished = match seq.as_slice() {
+            [b'[' | b'O'] => false,
+            [_] | [b'O', _] => true,
+            [_, .., last] => (0x40..=0x7e).contains(last),
+            [] => unreachable!(),
+        };
+        if finished {
+            break;
+        }
+    }
+
+    seq
 }
 
 fn parse_escape_sequence(sequence: &[u8]) -> Result<Key, SequenceParsingError> {
     if sequence[0] == b'[' {
         return match &sequence[1..] {
             b"3~" => Ok(Key::Delete),
+            b"H" | b"1~" => Ok(Key::Home),
+            b"F" | b"4~" => Ok(Key::End),
+            b"5~" => Ok(Key::PageUp),
+            b"6~" => Ok(Key::PageDown),
             b"A" => Ok(Key::ArrowUp),
             b"B" => Ok(Key::ArrowDown),
             b"C" => Ok(Key::ArrowRight),
@@ -87,6 +175,178 @@ fn parse_escape_sequence(sequence: &[u8]) -> Result<Key, SequenceParsingError> {
             _ => Err(SequenceParsingError::UnknownSequence(sequence.to_owned())),
         };
     }
+    if let [b'O', last] = sequence {
+        return match last {
+            b'A' => Ok(Key::ArrowUp),
+            b'B' => Ok(Key::ArrowDown),
+            b'C' => Ok(Key::ArrowRight),
+            b'D' => Ok(Key::ArrowLeft),
+            b'H' => Ok(Key::Home),
+            b'F' => Ok(Key::End),
+            _ => Err(SequenceParsingError::UnknownSequence(sequence.to_owned())),
+        };
+    }
+
+    Err(SequenceParsingError::UnknownSequence(sequence.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io::Read, time::Duration};
+
+    use crate::key::{Input, Key, KeyReader, SequenceParsingError};
+
+    /// Input arriving in bursts with a pause longer than the escape timeout between them
+    struct Bursts(VecDeque<&'static [u8]>);
+
+    impl Read for Bursts {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.0.front().is_some_and(|burst| burst.is_empty()) {
+                self.0.pop_front();
+            }
+            match self.0.front_mut() {
+                Some(burst) => burst.read(buf),
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Input for Bursts {
+        fn poll(&mut self, _timeout: Duration) -> bool {
+            match self.0.front() {
+                Some(burst) if !burst.is_empty() => true,
+                // The pause elapses
+                _ => {
+                    self.0.pop_front();
+                    false
+                }
+            }
+        }
+    }
 
-    Ok(Key::Escape)
+    #[test]
+    fn read_ascii() {
+        let mut input = KeyReader::new(b"a".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Char('a'))));
+    }
+
+    #[test]
+    fn read_escape() {
+        let mut input = KeyReader::new(b"\x1b[3~".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Delete)));
+
+        let mut input = KeyReader::new(b"\x1b".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+
+        let mut input = KeyReader::new(b"\x1b[A".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::ArrowUp)));
+
+        for seq in [b"\x1b[H".as_slice(), b"\x1b[1~"] {
+            let mut input = KeyReader::new(seq);
+            assert!(matches!(input.read_key(), Ok(Key::Home)));
+        }
+        for seq in [b"\x1b[F".as_slice(), b"\x1b[4~"] {
+            let mut input = KeyReader::new(seq);
+            assert!(matches!(input.read_key(), Ok(Key::End)));
+        }
+
+        let mut input = KeyReader::new(b"\x1b[5~\x1b[6~".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::PageUp)));
+        assert!(matches!(input.read_key(), Ok(Key::PageDown)));
+    }
+
+    #[test]
+    fn escape_timeout() {
+        // Esc typed alone then "[A", which must not become an arrow key
+        let mut input = KeyReader::new(Bursts(VecDeque::from([b"\x1b".as_slice(), b"[A"])));
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Char('['))));
+        assert!(matches!(input.read_key(), Ok(Key::Char('A'))));
+
+        // A sequence followed by more keys in the same burst
+        let mut input = KeyReader::new(b"\x1b[Dx\x1b[3~\x1b".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::ArrowLeft)));
+        assert!(matches!(input.read_key(), Ok(Key::Char('x'))));
+        assert!(matches!(input.read_key(), Ok(Key::Delete)));
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+
+        let mut input = KeyReader::new(b"\x1b[3".as_slice());
+        assert!(matches!(
+            input.read_key(),
+            Err(SequenceParsingError::UnknownSequence(seq)) if seq == b"[3"
+        ));
+    }
+
+    #[test]
+    fn key_after_escape() {
+        // Esc then j typed quickly, both keys are kept
+        let mut input = KeyReader::new(b"\x1bj\x1b\x1b\x1b\xc3\xa9".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Char('j'))));
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Char('é'))));
+    }
+
+    #[test]
+    fn read_ss3() {
+        let mut input = KeyReader::new(b"\x1bOA\x1bOD\x1bOH\x1bOF".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::ArrowUp)));
+        assert!(matches!(input.read_key(), Ok(Key::ArrowLeft)));
+        assert!(matches!(input.read_key(), Ok(Key::Home)));
+        assert!(matches!(input.read_key(), Ok(Key::End)));
+
+        // Esc then O to open a line
+        let mut input = KeyReader::new(b"\x1bO".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Escape)));
+        assert!(matches!(input.read_key(), Ok(Key::Char('O'))));
+
+        let mut input = KeyReader::new(b"\x1bOz".as_slice());
+        assert!(matches!(
+            input.read_key(),
+            Err(SequenceParsingError::UnknownSequence(seq)) if seq == b"Oz"
+        ));
+    }
+
+    #[test]
+    fn read_ctrl() {
+        let mut input = KeyReader::new(b"\x12\x01\x03\x13\t\r".as_slice());
+        assert!(matches!(input.read_key(), Ok(Key::Ctrl('r'))));
+        assert!(matches!(input.read_key(), Ok(Key::Ctrl('a'))));
+        // Without ISIG and IXON these are plain keys instead of signals
+        assert!(matches!(input.read_key(), Ok(Key::Ctrl('c'))));
+        assert!(matches!(input.read_key(), Ok(Key::Ctrl('s'))));
+        assert!(matches!(input.read_key(), Ok(Key::Tab)));
+        assert!(matches!(input.read_key(), Ok(Key::Enter)));
+    }
+
+    #[test]
+    fn read_utf8() {
+        let mut input = KeyReader::new("é日🆗".as_bytes());
+        assert!(matches!(input.read_key(), Ok(Key::Char('é'))));
+        assert!(matches!(input.read_key(), Ok(Key::Char('日'))));
+        assert!(matches!(input.read_key(), Ok(Key::Char('🆗'))));
+        assert!(matches!(
+            input.read_key(),
+            Err(SequenceParsingError::NoChar)
+        ));
+    }
+
+    #[test]
+    fn read_invalid_utf8() {
+        // Truncated sequence
+        let mut input = KeyReader::new([0xe6, 0x97].as_slice());
+        assert!(matches!(
+            input.read_key(),
+            Err(SequenceParsingError::InvalidUtf8(_))
+        ));
+
+        // Lone continuation byte
+        let mut input = KeyReader::new([0x97].as_slice());
+        assert!(matches!(
+            input.read_key(),
+            Err(SequenceParsingError::UnknownChar(0x97))
+        ));
+    }
 }
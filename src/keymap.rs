@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use crate::key::Key;
+
+/// Commands of Normal and Visual mode that keys are bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    Down,
+    Up,
+    /// `gj`, one screen row down when wrapping
+    DisplayDown,
+    /// `gk`, one screen row up when wrapping
+    DisplayUp,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    MatchingBracket,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    /// `G`, to the line of the count or the last one
+    GoToLine,
+    /// Waits for the next key, like `g`, `d` or `m`
+    Prefix(char),
+    /// Starts or stops recording a macro
+    RecordMacro,
+    ChangeToLineEnd,
+    JoinLines,
+    ToggleCase,
+    Increment,
+    Decrement,
+    RepeatChange,
+    PasteAfter,
+    PasteBefore,
+    VisualMode,
+    VisualLineMode,
+    DeleteChar,
+    Insert,
+    InsertAtLineStart,
+    Append,
+    AppendAtLineEnd,
+    OpenLineBelow,
+    OpenLineAbove,
+    Undo,
+    Redo,
+    OlderJump,
+    NewerJump,
+    FileInfo,
+    CommandLine,
+    Search,
+    SearchNext,
+    SearchPrevious,
+    ExitVisualMode,
+    DeleteSelection,
+    YankSelection,
+    // Commands of two keys, only reachable through a mapping
+    FirstLine,
+    DeleteLine,
+    YankLine,
+    WriteAndQuit,
+    QuitWithoutWriting,
+}
+
+/// Commands that take several keys in Vim, a mapping can bind them to a single one
+const SEQUENCES: &[(&str, Action)] = &[
+    ("gg", Action::FirstLine),
+    ("gj", Action::DisplayDown),
+    ("gk", Action::DisplayUp),
+    ("dd", Action::DeleteLine),
+    ("yy", Action::YankLine),
+    ("ZZ", Action::WriteAndQuit),
+    ("ZQ", Action::QuitWithoutWriting),
+];
+
+/// Motions are shared by Normal and Visual modes
+const MOTIONS: &[(Key, Action)] = &[
+    (Key::Char('h'), Action::Left),
+    (Key::ArrowLeft, Action::Left),
+    (Key::Backspace, Action::Left),
+    (Key::Char('l'), Action::Right),
+    (Key::ArrowRight, Action::Right),
+    (Key::Char('j'), Action::Down),
+    (Key::ArrowDown, Action::Down),
+    (Key::Enter, Action::Down),
+    (Key::Char('k'), Action::Up),
+    (Key::ArrowUp, Action::Up),
+    (Key::Char('w'), Action::WordForward),
+    (Key::Char('b'), Action::WordBackward),
+    (Key::Char('e'), Action::WordEnd),
+    (Key::Char('0'), Action::LineStart),
+    (Key::Home, Action::LineStart),
+    (Key::Char('$'), Action::LineEnd),
+    (Key::End, Action::LineEnd),
+    (Key::Char('%'), Action::MatchingBracket),
+    (Key::Ctrl('d'), Action::HalfPageDown),
+    (Key::Ctrl('u'), Action::HalfPageUp),
+    (Key::Ctrl('f'), Action::PageDown),
+    (Key::PageDown, Action::PageDown),
+    (Key::Ctrl('b'), Action::PageUp),
+    (Key::PageUp, Action::PageUp),
+    (Key::Char('G'), Action::GoToLine),
+];
+
+const NORMAL: &[(Key, Action)] = &[
+    (Key::Char('g'), Action::Prefix('g')),
+    (Key::Char('"'), Action::Prefix('"')),
+    (Key::Char('@'), Action::Prefix('@')),
+    (Key::Char('m'), Action::Prefix('m')),
+    (Key::Char('`'), Action::Prefix('`')),
+    (Key::Char('\''), Action::Prefix('\'')),
+    (Key::Char('d'), Action::Prefix('d')),
+    (Key::Char('c'), Action::Prefix('c')),
+    (Key::Char('y'), Action::Prefix('y')),
+    (Key::Char('r'), Action::Prefix('r')),
+    (Key::Char('z'), Action::Prefix('z')),
+    (Key::Char('Z'), Action::Prefix('Z')),
+    (Key::Char('q'), Action::RecordMacro),
+    (Key::Char('C'), Action::ChangeToLineEnd),
+    (Key::Char('J'), Action::JoinLines),
+    (Key::Char('~'), Action::ToggleCase),
+    (Key::Ctrl('a'), Action::Increment),
+    (Key::Ctrl('x'), Action::Decrement),
+    (Key::Char('.'), Action::RepeatChange),
+    (Key::Char('p'), Action::PasteAfter),
+    (Key::Char('P'), Action::PasteBefore),
+    (Key::Char('v'), Action::VisualMode),
+    (Key::Char('V'), Action::VisualLineMode),
+    (Key::Char('x'), Action::DeleteChar),
+    (Key::Delete, Action::DeleteChar),
+    (Key::Char('i'), Action::Insert),
+    (Key::Char('I'), Action::InsertAtLineStart),
+    (Key::Char('a'), Action::Append),
+    (Key::Char('A'), Action::AppendAtLineEnd),
+    (Key::Char('o'), Action::OpenLineBelow),
+    (Key::Char('O'), Action::OpenLineAbove),
+    (Key::Char('u'), Action::Undo),
+    (Key::Ctrl('r'), Action::Redo),
+    (Key::Ctrl('o'), Action::OlderJump),
+    // Terminals send Ctrl-I as Tab
+    (Key::Tab, Action::NewerJump),
+    (Key::Ctrl('g'), Action::FileInfo),
+    (Key::Char(':'), Action::CommandLine),
+    (Key::Char('/'), Action::Search),
+    (Key::Char('n'), Action::SearchNext),
+    (Key::Char('N'), Action::SearchPrevious),
+];
+
+const VISUAL: &[(Key, Action)] = &[
+    (Key::Escape, Action::ExitVisualMode),
+    (Key::Char('v'), Action::VisualMode),
+    (Key::Char('V'), Action::VisualLineMode),
+    (Key::Char('d'), Action::DeleteSelection),
+    (Key::Char('x'), Action::DeleteSelection),
+    (Key::Delete, Action::DeleteSelection),
+    (Key::Char('y'), Action::YankSelection),
+];
+
+/// Modes that `:map` and `:unmap` apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    /// Normal and Visual
+    All,
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    InvalidKey(String),
+    UnknownTarget(String),
+    NoSuchMapping(String),
+}
+
+/// Parses a key written like in Vim mappings, a character or a name like `<Esc>` or `<C-a>`
+#[must_use]
+pub fn parse_key(notation: &str) -> Option<Key> {
+    let mut chars = notation.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Char(c));
+    }
+
+    let name = notation
+        .strip_prefix('<')?
+        .strip_suffix('>')?
+        .to_ascii_lowercase();
+    let key = match name.as_str() {
+        "esc" => Key::Escape,
+        "cr" | "enter" | "return" | "c-j" | "c-m" => Key::Enter,
+        "tab" | "c-i" => Key::Tab,
+        "bs" | "c-h" => Key::Backspace,
+        "del" => Key::Delete,
+        "up" => Key::ArrowUp,
+        "down" => Key::ArrowDown,
+        "left" => Key::ArrowLeft,
+        "right" => Key::ArrowRight,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        name => match name.strip_prefix("c-")?.as_bytes() {
+            &[c @ b'a'..=b'z'] => Key::Ctrl(c as char),
+            _ => return None,
+        },
+    };
+
+    Some(key)
+}
+
+/// Actions bound to single keys in each mode
+/// Motions after an operator, like the `w` of `dw`, use the Normal mode bindings
+#[derive(Debug)]
+pub struct Keymap {
+    pub normal: HashMap<Key, Action>,
+    pub visual: HashMap<Key, Action>,
+}
+
+impl Keymap {
+    /// Binds `lhs` to the action `rhs` has when the mapping is made, so mappings don't
+    /// chain, `rhs` can also be one of the two-key commands of Normal mode like `gg`
+    pub fn map(&mut self, mode: MapMode, lhs: &str, rhs: &str) -> Result<(), MapError> {
+        let lhs = parse_key(lhs).ok_or_else(|| MapError::InvalidKey(lhs.to_owned()))?;
+        let rhs_key = parse_key(rhs);
+        let bound =
+            |table: &HashMap<Key, Action>| rhs_key.as_ref().and_then(|key| table.get(key)).copied();
+
+        let mut mapped = false;
+        if mode != MapMode::Visual
+            && let Some(action) = bound(&self.normal).or_else(|| {
+                SEQUENCES
+                    .iter()
+                    .find_map(|&(keys, action)| (keys == rhs).then_some(action))
+            })
+        {
+            self.normal.insert(lhs.clone(), action);
+            mapped = true;
+        }
+        if mode != MapMode::Normal
+            && let Some(action) = bound(&self.visual)
+        {
+            self.visual.insert(lhs, action);
+            mapped = true;
+        }
+
+        if mapped {
+            Ok(())
+        } else {
+            Err(MapError::UnknownTarget(rhs.to_owned()))
+        }
+    }
+
+    /// Gives `lhs` back its default binding, or none if it had none
+    pub fn unmap(&mut self, mode: MapMode, lhs: &str) -> Result<(), MapError> {
+        let key = parse_key(lhs).ok_or_else(|| MapError::InvalidKey(lhs.to_owned()))?;
+        let defaults = Keymap::default();
+
+        let mut unmapped = false;
+        let mut restore = |table: &mut HashMap<Key, Action>, defaults: &HashMap<Key, Action>| {
+            let default = defaults.get(&key).copied();
+            if table.get(&key).copied() == default {
+                return;
+            }
+            match default {
+                Some(action) => table.insert(key.clone(), action),
+                None => table.remove(&key),
+            };
+            unmapped = true;
+        };
+        if mode != MapMode::Visual {
+            restore(&mut self.normal, &defaults.normal);
+        }
+        if mode != MapMode::Normal {
+            restore(&mut self.visual, &defaults.visual);
+        }
+
+        if unmapped {
+            Ok(())
+        } else {
+            Err(MapError::NoSuchMapping(lhs.to_owned()))
+        }
+    }
+}
+
+impl Default for Keymap {
+    /// The bindings of Vim
+    fn default() -> Self {
+        Keymap {
+            normal: MOTIONS.iter().chain(NORMAL).cloned().collect(),
+            visual: MOTIONS.iter().chain(VISUAL).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        keymap::{Action, Keymap, MapError, MapMode, parse_key},
+        test_utils::{lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn key_notation() {
+        assert_eq!(parse_key("x"), Some(Key::Char('x')));
+        assert_eq!(parse_key("é"), Some(Key::Char('é')));
+        assert_eq!(parse_key("<"), Some(Key::Char('<')));
+        assert_eq!(parse_key("<Esc>"), Some(Key::Escape));
+        assert_eq!(parse_key("<cr>"), Some(Key::Enter));
+        assert_eq!(parse_key("<C-a>"), Some(Key::Ctrl('a')));
+        assert_eq!(parse_key("<C-I>"), Some(Key::Tab));
+        assert_eq!(parse_key("<Space>"), Some(Key::Char(' ')));
+        assert_eq!(parse_key("<C-1>"), None);
+        assert_eq!(parse_key("<Nope>"), None);
+        assert_eq!(parse_key("ab"), None);
+    }
+
+    #[test]
+    fn map_and_unmap() {
+        let mut keymap = Keymap::default();
+        keymap.map(MapMode::Normal, "s", "j").unwrap();
+        assert_eq!(keymap.normal.get(&Key::Char('s')), Some(&Action::Down));
+        assert!(!keymap.visual.contains_key(&Key::Char('s')));
+
+        // The right-hand side is resolved once
+        keymap.map(MapMode::Normal, "j", "k").unwrap();
+        assert_eq!(keymap.normal.get(&Key::Char('s')), Some(&Action::Down));
+        keymap.map(MapMode::All, "<C-n>", "gg").unwrap();
+        assert_eq!(keymap.normal.get(&Key::Ctrl('n')), Some(&Action::FirstLine));
+        assert!(!keymap.visual.contains_key(&Key::Ctrl('n')));
+        keymap.map(MapMode::All, "t", "<Esc>").unwrap();
+        assert_eq!(
+            keymap.visual.get(&Key::Char('t')),
+            Some(&Action::ExitVisualMode)
+        );
+        assert!(!keymap.normal.contains_key(&Key::Char('t')));
+
+        assert!(matches!(
+            keymap.map(MapMode::Normal, "s", "gx"),
+            Err(MapError::UnknownTarget(_))
+        ));
+        assert!(matches!(
+            keymap.map(MapMode::Normal, "ss", "j"),
+            Err(MapError::InvalidKey(_))
+        ));
+
+        keymap.unmap(MapMode::Normal, "j").unwrap();
+        assert_eq!(keymap.normal.get(&Key::Char('j')), Some(&Action::Down));
+        keymap.unmap(MapMode::Normal, "s").unwrap();
+        assert!(!keymap.normal.contains_key(&Key::Char('s')));
+        assert!(matches!(
+            keymap.unmap(MapMode::Normal, "s"),
+            Err(MapError::NoSuchMapping(_))
+        ));
+    }
+
+    #[test]
+    fn remap() {
+        let mut state = state_with_lines(&["abc", "def", "ghi"]);
+        // Dvorak-friendly movement
+        state.keymap.normal.insert(Key::Char('t'), Action::Down);
+        state.keymap.normal.insert(Key::Char('n'), Action::Right);
+        type_keys(&mut state, &[Key::Char('t'), Key::Char('n')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 1));
+        type_keys(&mut state, &[Key::Char('2'), Key::Char('t')]);
+        assert_eq!(state.current_line_index(), 2);
+
+        // Also after an operator and in Visual mode
+        type_keys(
+            &mut state,
+            &[Key::Char('0'), Key::Char('d'), Key::Char('n')],
+        );
+        assert_eq!(lines(&state), ["abc", "def", "hi"]);
+        state.keymap.visual.insert(Key::Char('n'), Action::Right);
+        type_keys(
+            &mut state,
+            &[Key::Char('v'), Key::Char('n'), Key::Char('d')],
+        );
+        assert_eq!(lines(&state), ["abc", "def", ""]);
+
+        // An unbound key does nothing
+        state.keymap.normal.remove(&Key::Char('x'));
+        type_keys(&mut state, &[Key::Char('k'), Key::Char('x')]);
+        assert_eq!(lines(&state), ["abc", "def", ""]);
+    }
+}
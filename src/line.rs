@@ -1,13 +1,152 @@
-use std::str::Chars;
+use std::{
+    convert::Infallible,
+    ops::Range,
+    str::{Chars, FromStr},
+};
 
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-#[derive(Debug)]
+use crate::regex::Regex;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    #[must_use]
+    pub fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            CharClass::Blank
+        } else if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+/// Display width of `c` when drawn at column `col`
+/// A tab extends to the next multiple of `tab_width`
+#[must_use]
+pub fn char_width(c: char, col: usize, tab_width: usize) -> usize {
+    if c == '\t' {
+        tab_width - col % tab_width
+    } else {
+        UnicodeWidthChar::width(c).unwrap_or(0)
+    }
+}
+
+/// Display width of `chars` drawn from column 0
+#[must_use]
+pub fn display_width<I: IntoIterator<Item = char>>(chars: I, tab_width: usize) -> usize {
+    chars
+        .into_iter()
+        .fold(0, |col, c| col + char_width(c, col, tab_width))
+}
+
+/// Display columns where the screen rows of `chars` wrapped at `text_cols` start
+/// Like in Vim, a double-width character that does not fit at the end of a row starts
+/// the next one, and `extra` columns are kept after the last character
+#[must_use]
+pub fn wrap_starts<I: IntoIterator<Item = char>>(
+    chars: I,
+    extra: usize,
+    text_cols: usize,
+    tab_width: usize,
+) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut row_start = 0;
+    let mut col = 0;
+    let wrap = |starts: &mut Vec<usize>, row_start: &mut usize, end: usize| {
+        while end > *row_start + text_cols {
+            *row_start += text_cols;
+            starts.push(*row_start);
+        }
+    };
+
+    for c in chars {
+        let width = char_width(c, col, tab_width);
+        if c != '\t' && width > 1 && col > row_start && col + width > row_start + text_cols {
+            row_start = col;
+            starts.push(col);
+        }
+        col += width;
+        wrap(&mut starts, &mut row_start, col);
+    }
+    wrap(&mut starts, &mut row_start, col + extra);
+
+    starts
+}
+
+/// Returns true if `text` starts with `needle`, letters being compared without case
+/// Characters are folded one at a time so that nothing is allocated
+fn starts_with_ignore_case(text: &str, needle: &str) -> bool {
+    let mut text = text.chars();
+    needle.chars().all(|n| {
+        text.next()
+            .is_some_and(|c| c == n || c.to_lowercase().eq(n.to_lowercase()))
+    })
+}
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Returns true if `c` never starts a grapheme cluster
+/// This covers combining marks, variation selectors, joiners and emoji modifiers
+fn is_grapheme_extend(c: char) -> bool {
+    UnicodeWidthChar::width(c) == Some(0) || ('\u{1f3fb}'..='\u{1f3ff}').contains(&c)
+}
+
+/// Two regional indicators form a flag
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Iterator over the grapheme clusters of a string
+/// This is an approximation of the Unicode rules that handles combining characters,
+/// emoji sequences and flags
+pub struct Graphemes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chars = self.rest.char_indices();
+        let (_, mut prev) = chars.next()?;
+        let mut regional_indicators = usize::from(is_regional_indicator(prev));
+
+        let mut end = self.rest.len();
+        for (i, c) in chars {
+            let is_flag_end =
+                is_regional_indicator(c) && is_regional_indicator(prev) && regional_indicators == 1;
+            if !(is_grapheme_extend(c) || prev == ZERO_WIDTH_JOINER || is_flag_end) {
+                end = i;
+                break;
+            }
+
+            regional_indicators += usize::from(is_regional_indicator(c));
+            prev = c;
+        }
+
+        let (grapheme, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(grapheme)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Line {
     text: String,
     has_utf8: bool,
+    has_tab: bool,
     len: usize,   // number of characters
-    width: usize, // unicode width of line
+    width: usize, // unicode width of line, not accounting for tabs
+    /// Bytes read from a file that were not valid UTF-8, dropped when the line changes
+    original: Option<Box<[u8]>>,
 }
 
 impl Line {
@@ -16,40 +155,61 @@ impl Line {
         Line {
             text: String::new(),
             has_utf8: false,
+            has_tab: false,
             len: 0,
             width: 0,
+            original: None,
         }
     }
 
     #[must_use]
     pub fn with_string(s: String) -> Self {
-        let has_utf8 = !s.is_ascii();
-
-        if has_utf8 {
-            Line {
-                has_utf8: true,
-                len: s.chars().count(),
-                width: UnicodeWidthStr::width(s.as_str()),
-                text: s,
-            }
-        } else {
-            Line {
-                has_utf8: false,
-                len: s.len(),
-                width: s.len(),
-                text: s,
+        let mut line = Line::new();
+        line.text = s;
+        line.update_metadata();
+        line
+    }
+
+    /// Decodes bytes read from a file, invalid UTF-8 sequences are shown as U+FFFD
+    /// but the original bytes are written back as long as the line is unchanged
+    #[must_use]
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Line::with_string(text),
+            Err(err) => {
+                let bytes = err.into_bytes();
+                let mut line = Line::with_string(String::from_utf8_lossy(&bytes).into_owned());
+                line.original = Some(bytes.into_boxed_slice());
+                line
             }
         }
     }
 
+    /// Returns true if the line was read from invalid UTF-8 and did not change since
     #[must_use]
-    pub fn get_unicode_width_at(&self, index: usize) -> usize {
+    pub const fn is_converted(&self) -> bool {
+        self.original.is_some()
+    }
+
+    /// Bytes to write to a file, the ones that were read for a converted line
+    #[must_use]
+    pub fn file_bytes(&self) -> &[u8] {
+        self.original.as_deref().unwrap_or(self.text.as_bytes())
+    }
+
+    /// Returns the display width of the first `index` characters
+    #[must_use]
+    pub fn get_unicode_width_at(&self, index: usize, tab_width: usize) -> usize {
+        if self.has_tab {
+            return display_width(self.chars().take(index), tab_width);
+        }
+
         if !self.has_utf8 {
             return index;
         }
 
         if index == self.len {
-            return self.width;
+            return self.width();
         }
 
         self.text
@@ -59,11 +219,250 @@ impl Line {
             .sum()
     }
 
+    /// Returns the index of the first character starting at or after the display column `col`
+    /// Tabs are not expanded
+    #[must_use]
+    pub fn index_at_width(&self, col: usize) -> usize {
+        if !self.has_utf8 {
+            return col.min(self.len);
+        }
+
+        let mut width = 0;
+        for (i, c) in self.char_enumerate() {
+            if width >= col {
+                return i;
+            }
+            width += UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+
+        self.len
+    }
+
+    /// Returns the part of the line starting at the codepoint `start_col` that fits in
+    /// `max_width` columns, a wide character that would not fit is left out
+    /// Tabs are not expanded
+    #[must_use]
+    pub fn truncate_to_width(&self, start_col: usize, max_width: usize) -> &str {
+        let start = self.byte_index_of(start_col);
+        if !self.has_utf8 {
+            return &self.text[start..(start + max_width).min(self.text.len())];
+        }
+
+        let mut width = 0;
+        let end = self.text[start..]
+            .char_indices()
+            .find(|&(_, c)| {
+                width += UnicodeWidthChar::width(c).unwrap_or(0);
+                width > max_width
+            })
+            .map_or(self.text.len(), |(i, _)| start + i);
+
+        &self.text[start..end]
+    }
+
+    #[must_use]
+    pub fn graphemes(&self) -> Graphemes<'_> {
+        Graphemes { rest: &self.text }
+    }
+
+    /// Returns the codepoint index of the grapheme cluster after the one at `index`
+    /// or the length of the line
+    #[must_use]
+    pub fn next_grapheme(&self, index: usize) -> usize {
+        if !self.has_utf8 {
+            return (index + 1).min(self.len);
+        }
+
+        let mut end = 0;
+        for grapheme in self.graphemes() {
+            end += grapheme.chars().count();
+            if end > index {
+                return end;
+            }
+        }
+
+        self.len
+    }
+
+    /// Returns the codepoint index of the last grapheme cluster starting before `index`
+    #[must_use]
+    pub fn prev_grapheme(&self, index: usize) -> usize {
+        if !self.has_utf8 {
+            return index.saturating_sub(1);
+        }
+
+        let mut prev = 0;
+        let mut start = 0;
+        for grapheme in self.graphemes() {
+            if start >= index {
+                break;
+            }
+            prev = start;
+            start += grapheme.chars().count();
+        }
+
+        prev
+    }
+
+    /// Returns the codepoint index where the grapheme cluster covering `index` starts
+    /// An index past the end is returned as is
+    #[must_use]
+    pub fn grapheme_start(&self, index: usize) -> usize {
+        if !self.has_utf8 {
+            return index;
+        }
+
+        let mut start = 0;
+        for grapheme in self.graphemes() {
+            let end = start + grapheme.chars().count();
+            if end > index {
+                return start;
+            }
+            start = end;
+        }
+
+        index
+    }
+
+    /// Codepoint index of every grapheme cluster with the class of its first character
+    fn grapheme_classes(&self) -> impl Iterator<Item = (usize, CharClass)> + '_ {
+        self.graphemes().scan(0, |start, grapheme| {
+            let index = *start;
+            *start += grapheme.chars().count();
+            grapheme.chars().next().map(|c| (index, CharClass::of(c)))
+        })
+    }
+
+    /// Returns the codepoint index of the first non-whitespace character
+    /// or the length of the line if it is blank
+    #[must_use]
+    pub fn first_non_blank(&self) -> usize {
+        self.chars()
+            .position(|c| !c.is_whitespace())
+            .unwrap_or(self.len)
+    }
+
+    /// Returns the codepoint index of the start of the word after `from`
+    #[must_use]
+    pub fn next_word_start(&self, from: usize) -> Option<usize> {
+        let from = self.grapheme_start(from);
+        let mut graphemes = self
+            .grapheme_classes()
+            .skip_while(|&(i, _)| i < from)
+            .peekable();
+        let (_, class) = graphemes.next()?;
+
+        if class != CharClass::Blank {
+            while graphemes.next_if(|&(_, c)| c == class).is_some() {}
+        }
+
+        graphemes
+            .find(|&(_, c)| c != CharClass::Blank)
+            .map(|(i, _)| i)
+    }
+
+    /// Returns the codepoint index of the last grapheme cluster of the first word ending at
+    /// or after `from`
+    #[must_use]
+    pub fn word_end(&self, from: usize) -> Option<usize> {
+        let from = self.grapheme_start(from);
+        let mut graphemes = self
+            .grapheme_classes()
+            .skip_while(|&(i, c)| i < from || c == CharClass::Blank)
+            .peekable();
+        let (mut end, class) = graphemes.next()?;
+
+        while let Some((i, _)) = graphemes.next_if(|&(_, c)| c == class) {
+            end = i;
+        }
+
+        Some(end)
+    }
+
+    /// Returns the codepoint index of the start of the word before `from`
+    #[must_use]
+    pub fn prev_word_start(&self, from: usize) -> Option<usize> {
+        let graphemes: Vec<(usize, CharClass)> = self
+            .grapheme_classes()
+            .take_while(|&(i, _)| i < from)
+            .collect();
+        let mut graphemes = graphemes
+            .into_iter()
+            .rev()
+            .skip_while(|&(_, c)| c == CharClass::Blank)
+            .peekable();
+        let (mut start, class) = graphemes.next()?;
+
+        while let Some((i, _)) = graphemes.next_if(|&(_, c)| c == class) {
+            start = i;
+        }
+
+        Some(start)
+    }
+
+    /// Returns the codepoint index of the first occurrence of `needle` at or after `from`
+    #[must_use]
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        let start = self.byte_index_of(from);
+        let found = self.text[start..].find(needle)? + start;
+
+        Some(from + self.text[start..found].chars().count())
+    }
+
+    /// Returns the codepoint index of the last occurrence of `needle` starting before `before`
+    #[must_use]
+    pub fn rfind(&self, needle: &str, before: usize) -> Option<usize> {
+        self.text
+            .char_indices()
+            .take(before)
+            .enumerate()
+            .filter(|&(_, (byte, _))| self.text[byte..].starts_with(needle))
+            .last()
+            .map(|(i, _)| i)
+    }
+
+    /// Same as `find` but letters match whatever their case
+    #[must_use]
+    pub fn find_ignore_case(&self, needle: &str, from: usize) -> Option<usize> {
+        self.text
+            .char_indices()
+            .enumerate()
+            .skip(from)
+            .find(|&(_, (byte, _))| starts_with_ignore_case(&self.text[byte..], needle))
+            .map(|(i, _)| i)
+    }
+
+    /// Same as `rfind` but letters match whatever their case
+    #[must_use]
+    pub fn rfind_ignore_case(&self, needle: &str, before: usize) -> Option<usize> {
+        self.text
+            .char_indices()
+            .take(before)
+            .enumerate()
+            .filter(|&(_, (byte, _))| starts_with_ignore_case(&self.text[byte..], needle))
+            .last()
+            .map(|(i, _)| i)
+    }
+
+    /// Returns the codepoint range of the first match of `regex` at or after `from`
+    #[must_use]
+    pub fn find_regex(&self, regex: &Regex, from: usize) -> Option<Range<usize>> {
+        let chars: Vec<char> = self.chars().collect();
+        regex.find_at(&chars, from)
+    }
+
     #[must_use]
     pub fn as_str(&self) -> &str {
         &self.text
     }
 
+    /// Spaces and tabs at the start of the line
+    #[must_use]
+    pub fn leading_whitespace(&self) -> &str {
+        let rest = self.text.trim_start_matches([' ', '\t']);
+        &self.text[..self.text.len() - rest.len()]
+    }
+
     #[must_use]
     pub fn as_bytes(&self) -> &[u8] {
         self.text.as_bytes()
@@ -74,15 +473,128 @@ impl Line {
         self.len
     }
 
+    /// Display width of the whole line, tabs count as one column
+    /// Use `get_unicode_width_at` with `len` to expand them
+    #[must_use]
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    pub const fn has_tab(&self) -> bool {
+        self.has_tab
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn chars(&self) -> Chars<'_> {
         self.text.chars()
     }
 
+    /// Characters with their codepoint index, which is what cursor columns are
+    /// Unlike `str::char_indices` the indices are not byte offsets
+    pub fn char_enumerate(&self) -> impl Iterator<Item = (usize, char)> + '_ {
+        self.text.chars().enumerate()
+    }
+
+    /// Characters before the codepoint `index`, from the closest one to the first
+    pub fn rchars_from(&self, index: usize) -> impl Iterator<Item = char> + '_ {
+        self.text[..self.byte_index_of(index)].chars().rev()
+    }
+
     pub fn clear(&mut self) {
         self.text.clear();
         self.len = 0;
         self.width = 0;
         self.has_utf8 = false;
+        self.has_tab = false;
+        self.original = None;
+    }
+
+    /// Removes the character at the codepoint `index`
+    pub fn remove_at(&mut self, index: usize) -> Option<char> {
+        let byte_index = self.byte_index_of(index);
+        if byte_index == self.text.len() {
+            return None;
+        }
+        let ch = self.text.remove(byte_index);
+        self.update_metadata();
+
+        Some(ch)
+    }
+
+    /// Replaces the character at the codepoint `index` with `ch` and returns the old one
+    pub fn replace_at(&mut self, index: usize, ch: char) -> Option<char> {
+        let byte_index = self.byte_index_of(index);
+        let old = self.text[byte_index..].chars().next()?;
+        self.text.replace_range(
+            byte_index..byte_index + old.len_utf8(),
+            ch.encode_utf8(&mut [0; 4]),
+        );
+        self.update_metadata();
+
+        Some(old)
+    }
+
+    /// Swaps the case of the character at the codepoint `index`
+    /// Returns the number of characters it became, e.g. 2 for 'ß' turning into "SS"
+    pub fn toggle_case_at(&mut self, index: usize) -> Option<usize> {
+        let byte_index = self.byte_index_of(index);
+        let c = self.text[byte_index..].chars().next()?;
+        let toggled: String = if c.is_uppercase() {
+            c.to_lowercase().collect()
+        } else if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else {
+            return Some(1);
+        };
+
+        self.text
+            .replace_range(byte_index..byte_index + c.len_utf8(), &toggled);
+        self.update_metadata();
+
+        Some(toggled.chars().count())
+    }
+
+    /// Inserts `text` at the codepoint `index`, the metadata is only updated once
+    /// Returns the index after it, or None if `index` is past the end of the line
+    pub fn insert_str(&mut self, index: usize, text: &str) -> Option<usize> {
+        if index > self.len {
+            return None;
+        }
+
+        let byte_index = self.byte_index_of(index);
+        self.text.insert_str(byte_index, text);
+        self.update_metadata();
+
+        Some(index + text.chars().count())
+    }
+
+    /// Truncates the line at the codepoint `index` and returns the tail
+    /// An `index` past the end returns an empty line
+    #[must_use]
+    pub fn split_off(&mut self, index: usize) -> Line {
+        let byte_index = self.byte_index_of(index);
+        let tail = Line::with_string(self.text.split_off(byte_index));
+        self.update_metadata();
+
+        tail
+    }
+
+    /// Removes the spaces and tabs at the end of the line
+    /// Returns true if anything was removed
+    pub fn trim_trailing_whitespace(&mut self) -> bool {
+        let trimmed = self.text.trim_end_matches([' ', '\t']).len();
+        if trimmed == self.text.len() {
+            return false;
+        }
+        self.text.truncate(trimmed);
+        self.update_metadata();
+
+        true
     }
 
     pub fn reserve(&mut self, additional: usize) {
@@ -91,16 +603,75 @@ impl Line {
 
     pub fn push(&mut self, ch: char) {
         self.text.push(ch);
+        self.original = None;
         self.len += 1;
+        self.has_tab |= ch == '\t';
+        // Control characters like U+0085 have no width but still take several bytes
+        self.has_utf8 |= !ch.is_ascii();
         if !ch.is_ascii()
             && let Some(width) = UnicodeWidthChar::width(ch)
         {
             self.width += width;
-            self.has_utf8 = true;
         } else {
             self.width += 1;
         }
     }
+
+    /// Converts a codepoint index to a byte offset into the text
+    /// An index at or past the end gives the length of the text
+    fn byte_index_of(&self, index: usize) -> usize {
+        if !self.has_utf8 {
+            return index.min(self.text.len());
+        }
+
+        self.text
+            .char_indices()
+            .nth(index)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    /// Recomputes `has_utf8`, `has_tab`, `len` and `width` from the text, which changed
+    fn update_metadata(&mut self) {
+        self.original = None;
+        self.has_utf8 = !self.text.is_ascii();
+        self.has_tab = self.text.contains('\t');
+        if self.has_utf8 {
+            self.len = self.text.chars().count();
+            self.width = UnicodeWidthStr::width(self.text.as_str());
+        } else {
+            self.len = self.text.len();
+            self.width = self.text.len();
+        }
+    }
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Line::new()
+    }
+}
+
+// The other fields are computed from the text or only matter for writing
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Line {}
+
+impl From<&str> for Line {
+    fn from(s: &str) -> Self {
+        Line::with_string(s.to_owned())
+    }
+}
+
+impl FromStr for Line {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Line::from(s))
+    }
 }
 
 impl Extend<char> for Line {
@@ -111,3 +682,496 @@ impl Extend<char> for Line {
         iterator.for_each(move |c| self.push(c));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        line::{Line, wrap_starts},
+        regex::Regex,
+    };
+
+    #[test]
+    fn wrap_rows() {
+        assert_eq!(wrap_starts("".chars(), 1, 4, 8), [0]);
+        assert_eq!(wrap_starts("abcd".chars(), 0, 4, 8), [0]);
+        assert_eq!(wrap_starts("abcd".chars(), 1, 4, 8), [0, 4]);
+        assert_eq!(wrap_starts("abcdefghi".chars(), 0, 4, 8), [0, 4, 8]);
+        // The wide character goes on the next row
+        assert_eq!(wrap_starts("abc日本".chars(), 0, 4, 8), [0, 3]);
+        assert_eq!(wrap_starts("abc日本x".chars(), 0, 4, 8), [0, 3, 7]);
+        assert_eq!(wrap_starts("ab日本".chars(), 0, 4, 8), [0, 4]);
+        // Tabs are cut
+        assert_eq!(wrap_starts("ab\tc".chars(), 0, 4, 8), [0, 4, 8]);
+    }
+
+    #[test]
+    fn width_with_tabs() {
+        let line = Line::with_string("\tab\tc日\td".to_owned());
+        assert_eq!(line.get_unicode_width_at(0, 4), 0);
+        assert_eq!(line.get_unicode_width_at(1, 4), 4);
+        assert_eq!(line.get_unicode_width_at(3, 4), 6);
+        assert_eq!(line.get_unicode_width_at(4, 4), 8);
+        assert_eq!(line.get_unicode_width_at(6, 4), 11);
+        assert_eq!(line.get_unicode_width_at(7, 4), 12);
+        assert_eq!(line.get_unicode_width_at(8, 4), 13);
+        assert_eq!(line.get_unicode_width_at(8, 8), 25);
+
+        let mut line = Line::new();
+        line.extend("a\tb".chars());
+        assert_eq!(line.get_unicode_width_at(3, 8), 9);
+        line.clear();
+        line.extend("ab".chars());
+        assert_eq!(line.get_unicode_width_at(2, 8), 2);
+    }
+
+    #[test]
+    fn width() {
+        let mut line = Line::from("日本語 text");
+        assert_eq!(line.len(), 8);
+        assert_eq!(line.width(), 11);
+
+        line.push('字');
+        assert_eq!(line.width(), 13);
+        line.remove_at(0);
+        assert_eq!(line.width(), 11);
+        assert_eq!(line.width(), line.get_unicode_width_at(line.len(), 4));
+
+        assert_eq!(Line::from("ascii").width(), 5);
+        assert_eq!(Line::new().width(), 0);
+    }
+
+    #[test]
+    fn from_str() {
+        for text in ["", "ascii", "日本\tb", "🆗"] {
+            let owned = Line::with_string(text.to_owned());
+            let parsed: Line = text.parse().unwrap();
+            let converted = Line::from(text);
+            for line in [parsed, converted] {
+                assert_eq!(line.as_str(), owned.as_str());
+                assert_eq!(line.len(), owned.len());
+                assert_eq!(line.has_tab(), owned.has_tab());
+                assert_eq!(
+                    line.get_unicode_width_at(line.len(), 4),
+                    owned.get_unicode_width_at(owned.len(), 4)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(Line::default(), Line::new());
+        assert_eq!(Line::default(), Line::from(""));
+
+        let mut pushed = Line::new();
+        pushed.extend("a日\tc".chars());
+        let mut edited = Line::from("ab日\tc");
+        edited.remove_at(1);
+        assert_eq!(pushed, Line::from("a日\tc"));
+        assert_eq!(pushed, edited);
+        assert_ne!(pushed, Line::from("a日 c"));
+
+        let mut cleared = Line::from("text");
+        cleared.clear();
+        assert_eq!(cleared, Line::default());
+    }
+
+    #[test]
+    fn graphemes() {
+        // e + combining acute, flag, family emoji, thumbs up with skin tone
+        let line = Line::from("ae\u{301}🇫🇷🇯🇵👨\u{200d}👩\u{200d}👧👍🏽b");
+        let graphemes: Vec<&str> = line.graphemes().collect();
+        assert_eq!(
+            graphemes,
+            [
+                "a",
+                "e\u{301}",
+                "🇫🇷",
+                "🇯🇵",
+                "👨\u{200d}👩\u{200d}👧",
+                "👍🏽",
+                "b"
+            ]
+        );
+        assert_eq!(Line::from("ascii").graphemes().count(), 5);
+        assert_eq!(Line::new().graphemes().next(), None);
+    }
+
+    #[test]
+    fn grapheme_boundaries() {
+        let line = Line::from("ae\u{301}🇫🇷b");
+        assert_eq!(line.next_grapheme(0), 1);
+        assert_eq!(line.next_grapheme(1), 3);
+        assert_eq!(line.next_grapheme(2), 3);
+        assert_eq!(line.next_grapheme(3), 5);
+        assert_eq!(line.next_grapheme(5), 6);
+        assert_eq!(line.next_grapheme(6), 6);
+
+        assert_eq!(line.prev_grapheme(6), 5);
+        assert_eq!(line.prev_grapheme(5), 3);
+        assert_eq!(line.prev_grapheme(3), 1);
+        assert_eq!(line.prev_grapheme(2), 1);
+        assert_eq!(line.prev_grapheme(1), 0);
+        assert_eq!(line.prev_grapheme(0), 0);
+
+        assert_eq!(line.grapheme_start(2), 1);
+        assert_eq!(line.grapheme_start(4), 3);
+        assert_eq!(line.grapheme_start(5), 5);
+        assert_eq!(line.grapheme_start(6), 6);
+    }
+
+    #[test]
+    fn words_of_composed_characters() {
+        let line = Line::from("cafe\u{301} ab\u{301}");
+        assert_eq!(line.next_word_start(0), Some(6));
+        assert_eq!(line.next_word_start(4), Some(6));
+        assert_eq!(line.word_end(0), Some(3));
+        assert_eq!(line.word_end(5), Some(7));
+        assert_eq!(line.prev_word_start(9), Some(6));
+        assert_eq!(line.prev_word_start(6), Some(0));
+    }
+
+    #[test]
+    fn is_empty() {
+        assert!(Line::new().is_empty());
+
+        let mut line = Line::with_string("été".to_owned());
+        assert!(!line.is_empty());
+        line.clear();
+        assert!(line.is_empty());
+
+        let mut line = Line::with_string("a".to_owned());
+        line.remove_at(0);
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn truncate_to_width() {
+        let line = Line::with_string("hello world".to_owned());
+        assert_eq!(line.truncate_to_width(0, 5), "hello");
+        assert_eq!(line.truncate_to_width(6, 20), "world");
+        assert_eq!(line.truncate_to_width(20, 5), "");
+
+        let line = Line::with_string("a日本🆗b".to_owned());
+        assert_eq!(line.truncate_to_width(0, 3), "a日");
+        // The wide character sits exactly on the boundary
+        assert_eq!(line.truncate_to_width(0, 4), "a日");
+        assert_eq!(line.truncate_to_width(0, 5), "a日本");
+        assert_eq!(line.truncate_to_width(1, 1), "");
+        assert_eq!(line.truncate_to_width(3, 2), "🆗");
+        assert_eq!(line.truncate_to_width(3, 10), "🆗b");
+        assert_eq!(line.truncate_to_width(5, 10), "");
+    }
+
+    #[test]
+    fn index_at_width() {
+        let line = Line::with_string("a日本b".to_owned());
+        assert_eq!(line.index_at_width(0), 0);
+        assert_eq!(line.index_at_width(1), 1);
+        // Column 2 is the second half of 日
+        assert_eq!(line.index_at_width(2), 2);
+        assert_eq!(line.index_at_width(3), 2);
+        assert_eq!(line.index_at_width(5), 3);
+        assert_eq!(line.index_at_width(10), 4);
+        assert_eq!(Line::with_string("abc".to_owned()).index_at_width(5), 3);
+    }
+
+    #[test]
+    fn first_non_blank() {
+        assert_eq!(
+            Line::with_string("    indented".to_owned()).first_non_blank(),
+            4
+        );
+        assert_eq!(Line::with_string("\t word".to_owned()).first_non_blank(), 2);
+        assert_eq!(Line::with_string("none".to_owned()).first_non_blank(), 0);
+        assert_eq!(Line::with_string("   ".to_owned()).first_non_blank(), 3);
+        assert_eq!(Line::new().first_non_blank(), 0);
+    }
+
+    #[test]
+    fn find() {
+        let line = Line::with_string("日本 foo foo".to_owned());
+        assert_eq!(line.find("foo", 0), Some(3));
+        assert_eq!(line.find("foo", 4), Some(7));
+        assert_eq!(line.find("foo", 8), None);
+        assert_eq!(line.find("本", 0), Some(1));
+        assert_eq!(line.find("foo", 20), None);
+
+        assert_eq!(line.rfind("foo", 11), Some(7));
+        assert_eq!(line.rfind("foo", 7), Some(3));
+        assert_eq!(line.rfind("foo", 3), None);
+        assert_eq!(line.rfind("日", 1), Some(0));
+    }
+
+    #[test]
+    fn find_ignore_case() {
+        let line = Line::from("Été foo FOO fOo");
+        assert_eq!(line.find_ignore_case("éTÉ", 0), Some(0));
+        assert_eq!(line.find_ignore_case("Foo", 1), Some(4));
+        assert_eq!(line.find_ignore_case("foo", 5), Some(8));
+        assert_eq!(line.find_ignore_case("foo", 13), None);
+        assert_eq!(line.find_ignore_case("fooo", 0), None);
+
+        assert_eq!(line.rfind_ignore_case("FOO", 15), Some(12));
+        assert_eq!(line.rfind_ignore_case("FOO", 12), Some(8));
+        assert_eq!(line.rfind_ignore_case("été", 0), None);
+    }
+
+    #[test]
+    fn trim_trailing_whitespace() {
+        let mut line = Line::from("日本 \t x\t\t");
+        assert!(line.trim_trailing_whitespace());
+        assert_eq!(line.as_str(), "日本 \t x");
+        assert_eq!((line.len(), line.width()), (6, 8));
+        assert!(!line.trim_trailing_whitespace());
+
+        let mut line = Line::from(" \t  ");
+        assert!(line.trim_trailing_whitespace());
+        assert!(line.is_empty());
+        assert_eq!(line.width(), 0);
+
+        let mut line = Line::from("é\t");
+        assert!(line.trim_trailing_whitespace());
+        assert_eq!(line.as_str(), "é");
+        assert!(!Line::new().trim_trailing_whitespace());
+    }
+
+    #[test]
+    fn from_bytes() {
+        let line = Line::from_bytes("été".as_bytes().to_vec());
+        assert!(!line.is_converted());
+        assert_eq!(line.file_bytes(), "été".as_bytes());
+
+        // Latin-1
+        let mut line = Line::from_bytes(b"caf\xe9 \xff!".to_vec());
+        assert!(line.is_converted());
+        assert_eq!(line.as_str(), "caf\u{fffd} \u{fffd}!");
+        assert_eq!((line.len(), line.width()), (7, 7));
+        assert_eq!(line.file_bytes(), b"caf\xe9 \xff!");
+
+        line.push('?');
+        assert!(!line.is_converted());
+        assert_eq!(line.file_bytes(), "caf\u{fffd} \u{fffd}!?".as_bytes());
+        let mut line = Line::from_bytes(vec![0x80]);
+        line.remove_at(0);
+        assert!(!line.is_converted());
+    }
+
+    #[test]
+    fn find_regex() {
+        let line = Line::from("日本 x12 y3");
+        let regex = Regex::new(r"[a-z]\d+", false).unwrap();
+        assert_eq!(line.find_regex(&regex, 0), Some(3..6));
+        assert_eq!(line.find_regex(&regex, 4), Some(7..9));
+        assert_eq!(line.find_regex(&regex, 9), None);
+    }
+
+    #[test]
+    fn next_word_start() {
+        let line = Line::with_string("foo bar.baz  (été)".to_owned());
+        assert_eq!(line.next_word_start(0), Some(4));
+        assert_eq!(line.next_word_start(1), Some(4));
+        assert_eq!(line.next_word_start(4), Some(7));
+        assert_eq!(line.next_word_start(7), Some(8));
+        assert_eq!(line.next_word_start(8), Some(13));
+        assert_eq!(line.next_word_start(13), Some(14));
+        assert_eq!(line.next_word_start(14), Some(17));
+        assert_eq!(line.next_word_start(17), None);
+
+        let line = Line::with_string("word   ".to_owned());
+        assert_eq!(line.next_word_start(0), None);
+        assert_eq!(Line::new().next_word_start(0), None);
+    }
+
+    #[test]
+    fn char_enumerate() {
+        let line = Line::from("aé日🆗b");
+        let indices: Vec<(usize, char)> = line.char_enumerate().collect();
+        assert_eq!(
+            indices,
+            [(0, 'a'), (1, 'é'), (2, '日'), (3, '🆗'), (4, 'b')]
+        );
+        assert_eq!(line.as_str().char_indices().last(), Some((10, 'b')));
+        assert_eq!(Line::new().char_enumerate().next(), None);
+    }
+
+    #[test]
+    fn rchars_from() {
+        let line = Line::from("aé日🆗b");
+        assert_eq!(line.rchars_from(4).collect::<String>(), "🆗日éa");
+        assert_eq!(line.rchars_from(2).collect::<String>(), "éa");
+        assert_eq!(line.rchars_from(10).collect::<String>(), "b🆗日éa");
+        assert_eq!(line.rchars_from(0).next(), None);
+        assert_eq!(Line::new().rchars_from(3).next(), None);
+    }
+
+    #[test]
+    fn word_end() {
+        let line = Line::with_string("foo bar.baz  (été)  ".to_owned());
+        assert_eq!(line.word_end(0), Some(2));
+        assert_eq!(line.word_end(3), Some(6));
+        assert_eq!(line.word_end(7), Some(7));
+        assert_eq!(line.word_end(8), Some(10));
+        assert_eq!(line.word_end(11), Some(13));
+        assert_eq!(line.word_end(14), Some(16));
+        assert_eq!(line.word_end(17), Some(17));
+        assert_eq!(line.word_end(18), None);
+
+        let line = Line::with_string("!?! a".to_owned());
+        assert_eq!(line.word_end(1), Some(2));
+        assert_eq!(line.word_end(3), Some(4));
+        assert_eq!(Line::new().word_end(0), None);
+    }
+
+    #[test]
+    fn prev_word_start() {
+        let line = Line::with_string("foo bar.baz  (été)".to_owned());
+        assert_eq!(line.prev_word_start(18), Some(17));
+        assert_eq!(line.prev_word_start(17), Some(14));
+        assert_eq!(line.prev_word_start(15), Some(14));
+        assert_eq!(line.prev_word_start(14), Some(13));
+        assert_eq!(line.prev_word_start(13), Some(8));
+        assert_eq!(line.prev_word_start(8), Some(7));
+        assert_eq!(line.prev_word_start(7), Some(4));
+        assert_eq!(line.prev_word_start(4), Some(0));
+        assert_eq!(line.prev_word_start(0), None);
+
+        let line = Line::with_string("   word".to_owned());
+        assert_eq!(line.prev_word_start(3), None);
+        assert_eq!(Line::new().prev_word_start(0), None);
+    }
+
+    #[test]
+    fn remove_at() {
+        let mut line = Line::with_string("hello".to_owned());
+        assert_eq!(line.remove_at(1), Some('e'));
+        assert_eq!(line.as_str(), "hllo");
+        assert_eq!(line.len(), 4);
+        assert_eq!(line.remove_at(4), None);
+
+        let mut line = Line::with_string("a日b".to_owned());
+        assert_eq!(line.get_unicode_width_at(3, 4), 4);
+        assert_eq!(line.remove_at(1), Some('日'));
+        assert_eq!(line.as_str(), "ab");
+        assert_eq!(line.len(), 2);
+        assert_eq!(line.get_unicode_width_at(2, 4), 2);
+
+        let mut line = Line::new();
+        assert_eq!(line.remove_at(0), None);
+    }
+
+    #[test]
+    fn replace_at() {
+        let mut line = Line::with_string("abc".to_owned());
+        assert_eq!(line.replace_at(1, '日'), Some('b'));
+        assert_eq!(line.as_str(), "a日c");
+        assert_eq!(line.len(), 3);
+        assert_eq!(line.get_unicode_width_at(3, 4), 4);
+
+        assert_eq!(line.replace_at(1, 'b'), Some('日'));
+        assert_eq!(line.as_str(), "abc");
+        assert_eq!(line.get_unicode_width_at(3, 4), 3);
+        assert_eq!(line.replace_at(3, 'd'), None);
+
+        assert_eq!(Line::new().replace_at(0, 'a'), None);
+    }
+
+    #[test]
+    fn leading_whitespace() {
+        assert_eq!(Line::from("  \t text ").leading_whitespace(), "  \t ");
+        assert_eq!(Line::from("text").leading_whitespace(), "");
+        assert_eq!(Line::from("\t\t").leading_whitespace(), "\t\t");
+        assert_eq!(Line::new().leading_whitespace(), "");
+    }
+
+    #[test]
+    fn toggle_case_at() {
+        let mut line = Line::from("aB1é");
+        assert_eq!(line.toggle_case_at(0), Some(1));
+        assert_eq!(line.toggle_case_at(1), Some(1));
+        assert_eq!(line.toggle_case_at(2), Some(1));
+        assert_eq!(line.toggle_case_at(3), Some(1));
+        assert_eq!(line.as_str(), "Ab1É");
+        assert_eq!(line.toggle_case_at(4), None);
+
+        let mut line = Line::from("straße");
+        assert_eq!(line.toggle_case_at(4), Some(2));
+        assert_eq!(line.as_str(), "straSSe");
+        assert_eq!(line.len(), 7);
+        assert_eq!(line.get_unicode_width_at(7, 4), 7);
+    }
+
+    #[test]
+    fn byte_index_of() {
+        let line = Line::from("abc");
+        assert_eq!(line.byte_index_of(0), 0);
+        assert_eq!(line.byte_index_of(2), 2);
+        assert_eq!(line.byte_index_of(3), 3);
+        assert_eq!(line.byte_index_of(10), 3);
+
+        let line = Line::from("aé日🆗b");
+        assert_eq!(line.byte_index_of(1), 1);
+        assert_eq!(line.byte_index_of(2), 3);
+        assert_eq!(line.byte_index_of(3), 6);
+        assert_eq!(line.byte_index_of(4), 10);
+        assert_eq!(line.byte_index_of(5), 11);
+        assert_eq!(line.byte_index_of(6), 11);
+
+        assert_eq!(Line::new().byte_index_of(0), 0);
+    }
+
+    #[test]
+    fn push_control_character() {
+        let mut line = Line::new();
+        line.extend("a\u{85}b".chars());
+        assert_eq!(line.insert_str(2, "x"), Some(3));
+        assert_eq!(line.as_str(), "a\u{85}xb");
+        assert_eq!(line.rchars_from(2).next(), Some('\u{85}'));
+    }
+
+    #[test]
+    fn insert_str() {
+        let mut line = Line::from("hd");
+        assert_eq!(line.insert_str(1, "ello worl"), Some(10));
+        assert_eq!(line.as_str(), "hello world");
+        assert_eq!(line.insert_str(11, " 日本"), Some(14));
+        assert_eq!(line.len(), 14);
+        assert_eq!(line.get_unicode_width_at(14, 4), 16);
+        assert_eq!(line.insert_str(15, "x"), None);
+        assert_eq!(line.as_str(), "hello world 日本");
+
+        let mut line = Line::from("日b");
+        assert_eq!(line.insert_str(1, "a"), Some(2));
+        assert_eq!(line.insert_str(0, "\t"), Some(1));
+        assert_eq!(line.as_str(), "\t日ab");
+        assert_eq!(line.get_unicode_width_at(4, 4), 8);
+    }
+
+    #[test]
+    fn split_off() {
+        let mut line = Line::with_string("hello world".to_owned());
+        let tail = line.split_off(5);
+        assert_eq!(line.as_str(), "hello");
+        assert_eq!(tail.as_str(), " world");
+        assert_eq!(tail.len(), 6);
+
+        let mut line = Line::with_string("日本\tb".to_owned());
+        let tail = line.split_off(1);
+        assert_eq!(line.as_str(), "日");
+        assert_eq!(line.len(), 1);
+        assert_eq!(line.get_unicode_width_at(1, 4), 2);
+        assert_eq!(tail.as_str(), "本\tb");
+        assert_eq!(tail.get_unicode_width_at(3, 4), 5);
+
+        let mut line = Line::with_string("été".to_owned());
+        let tail = line.split_off(0);
+        assert_eq!(line.len(), 0);
+        assert_eq!(line.get_unicode_width_at(0, 4), 0);
+        assert_eq!(tail.as_str(), "été");
+
+        let mut line = Line::with_string("été".to_owned());
+        let tail = line.split_off(3);
+        assert_eq!(line.as_str(), "été");
+        assert_eq!(tail.len(), 0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::{Message, MessageType, State, key::Key};
+
+/// Keys recorded with `q` and played back with `@`
+#[derive(Debug, Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<Key>>,
+    /// Register being recorded and the keys typed so far
+    recording: Option<(char, Vec<Key>)>,
+    /// Registers being played, the innermost last
+    playing: Vec<char>,
+    last_played: Option<char>,
+    /// Number of nested replays, replayed keys are not recorded again
+    replay_depth: usize,
+}
+
+impl State {
+    /// Name of the register being recorded, if any
+    pub fn recording_macro(&self) -> Option<char> {
+        self.macros.recording.as_ref().map(|&(name, _)| name)
+    }
+
+    /// An uppercase register name appends to the lowercase register
+    pub fn start_macro_recording(&mut self, name: char) {
+        let lowercase = name.to_ascii_lowercase();
+        let keys = if name.is_ascii_uppercase() {
+            self.macros.registers.remove(&lowercase).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.macros.recording = Some((lowercase, keys));
+    }
+
+    pub fn stop_macro_recording(&mut self) {
+        if let Some((name, mut keys)) = self.macros.recording.take() {
+            // Drop the `q` that stopped the recording
+            keys.pop();
+            self.macros.registers.insert(name, keys);
+        }
+    }
+
+    /// Must be called with every key before handling it
+    pub fn record_macro_key(&mut self, key: &Key) {
+        if self.macros.replay_depth == 0
+            && let Some((_, keys)) = &mut self.macros.recording
+        {
+            keys.push(key.clone());
+        }
+    }
+
+    /// Handles `keys` as if they were typed, used by `@` and `.`
+    pub fn replay_keys(&mut self, keys: &[Key]) {
+        self.macros.replay_depth += 1;
+        for key in keys {
+            self.handle_keypress(key);
+        }
+        self.macros.replay_depth -= 1;
+    }
+
+    /// Plays the register `name` `count` times, `@` plays the last played register again
+    pub fn play_macro(&mut self, name: char, count: usize) {
+        let name = if name == '@' {
+            let Some(last) = self.macros.last_played else {
+                self.message = Message {
+                    msg: "No previously used register".to_owned(),
+                    r#type: MessageType::Error,
+                };
+                return;
+            };
+            last
+        } else {
+            name.to_ascii_lowercase()
+        };
+
+        // A macro playing itself would never end
+        if self.macros.playing.contains(&name) {
+            self.message = Message {
+                msg: format!("Recursive macro @{name}"),
+                r#type: MessageType::Error,
+            };
+            return;
+        }
+        let Some(keys) = self.macros.registers.get(&name).cloned() else {
+            return;
+        };
+
+        self.macros.last_played = Some(name);
+        self.macros.playing.push(name);
+        for _ in 0..count {
+            self.replay_keys(&keys);
+        }
+        self.macros.playing.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{keys, lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn record_and_play() {
+        let mut state = state_with_lines(&["one", "two", "three", "four", "five"]);
+        type_keys(&mut state, &keys("qaA!"));
+        type_keys(&mut state, &[Key::Escape, Key::Char('j'), Key::Char('q')]);
+        assert_eq!(state.recording_macro(), None);
+        assert_eq!(lines(&state), ["one!", "two", "three", "four", "five"]);
+
+        type_keys(&mut state, &keys("@a"));
+        assert_eq!(lines(&state), ["one!", "two!", "three", "four", "five"]);
+        type_keys(&mut state, &keys("2@@"));
+        assert_eq!(lines(&state), ["one!", "two!", "three!", "four!", "five"]);
+
+        // Unknown registers do nothing
+        type_keys(&mut state, &keys("@z"));
+        assert_eq!(state.current_line_index(), 4);
+    }
+
+    #[test]
+    fn append_to_macro() {
+        let mut state = state_with_lines(&["abcdef"]);
+        type_keys(&mut state, &keys("qbxqqBxq"));
+        assert_eq!(lines(&state), ["cdef"]);
+
+        type_keys(&mut state, &keys("@b"));
+        assert_eq!(lines(&state), ["ef"]);
+    }
+
+    #[test]
+    fn replayed_keys_are_not_recorded() {
+        let mut state = state_with_lines(&["abcdef"]);
+        type_keys(&mut state, &keys("qcx.q"));
+        assert_eq!(lines(&state), ["cdef"]);
+        type_keys(&mut state, &keys("qd@cq"));
+        assert_eq!(lines(&state), ["ef"]);
+
+        // @d only holds `@c`
+        type_keys(&mut state, &keys("@d"));
+        assert_eq!(lines(&state), [""]);
+    }
+
+    #[test]
+    fn recursive_macro() {
+        let mut state = state_with_lines(&["abcdef"]);
+        type_keys(&mut state, &keys("qex@eq"));
+        assert_eq!(lines(&state), ["bcdef"]);
+
+        type_keys(&mut state, &keys("@e"));
+        assert_eq!(lines(&state), ["cdef"]);
+        assert_eq!(state.message.msg, "Recursive macro @e");
+    }
+}
@@ -1,46 +1,91 @@
-use color_eyre::eyre::{Context, ContextCompat, OptionExt};
+use color_eyre::eyre::Context;
 use log::{debug, warn};
 use std::{
-    collections::VecDeque,
-    fs::File,
-    io::{BufRead, BufReader, Write, stdout},
+    collections::HashMap,
+    io::{Write, stdout},
+    ops::RangeInclusive,
     path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
 };
-use unicode_width::UnicodeWidthChar;
 
 use cvt::cvt;
-use libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO, TCSAFLUSH, TCSANOW, TIOCGWINSZ};
+use libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO, TIOCGWINSZ};
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
     command_parser::Command,
-    key::{Key, SequenceParsingError, read_key},
-    line::Line,
+    config::Config,
+    edit::Position,
+    gap_buffer::GapBuffer,
+    highlight::{Highlighter, Span},
+    history::History,
+    jumps::JumpList,
+    key::{Key, KeyReader, SequenceParsingError},
+    keymap::{Action, Keymap},
+    line::{Line, char_width, wrap_starts},
     logger::setup_logger,
+    macros::Macros,
+    register::RegisterContent,
+    repeat::ChangeRecorder,
+    search::{Direction, Pattern},
+    terminal::{RawTerminal, TerminalInput},
+    theme::{Background, Foreground, Rgb, Theme},
+    utils::{FileContent, load_file},
 };
 
+mod brackets;
 mod command_parser;
+mod config;
+mod edit;
+mod gap_buffer;
+mod highlight;
+mod history;
+mod increment;
+mod jumps;
 mod key;
+mod keymap;
 mod line;
 mod logger;
+mod macros;
+mod marks;
+mod operator;
+mod regex;
+mod register;
+mod repeat;
+mod search;
+mod substitute;
+mod terminal;
+#[cfg(test)]
+mod test_utils;
+mod theme;
 mod utils;
+mod visual;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 struct WindowSize {
     col: usize,
     row: usize,
 }
 
-#[derive(Debug)]
-struct SplitBuffer {
-    start: Vec<char>,
-    end: VecDeque<char>,
-}
-
 #[derive(Debug)]
 enum Mode {
     Normal,
-    Insertion { buffer: SplitBuffer },
+    Insertion {
+        buffer: GapBuffer,
+    },
     Command,
+    /// `origin` is where the cursor was before it moved to the first match, and `view`
+    /// the `text_offset` and `col_offset` it was seen with
+    Search {
+        origin: Position,
+        view: (usize, usize),
+    },
+    /// `anchor` is the position in the buffer where the selection started,
+    /// `linewise` selects whole lines
+    Visual {
+        anchor: WindowSize,
+        linewise: bool,
+    },
 }
 
 #[allow(dead_code)]
@@ -59,38 +104,62 @@ struct Message {
 
 #[derive(Debug)]
 struct State {
-    previous_io_settings: libc::termios,
-    current_io_settings: libc::termios,
     window_size: WindowSize,
     cursor_pos: WindowSize,
     target_col: usize,
+    /// Screen column wanted by `gj` and `gk`, kept while the cursor stays where they left it
+    display_col: Option<(usize, Position)>,
     text_lines: Vec<Line>,
     text_offset: usize,
+    /// Display column of the first visible column of text
+    col_offset: usize,
+    /// Text rows of the last frame, unchanged rows are not redrawn
+    previous_rows: Vec<Vec<u8>>,
     current_mode: Mode,
     command_buf: String,
     message: Message,
     save_file: Option<PathBuf>,
+    /// The file ended with a newline when it was read
+    end_of_line: bool,
+    /// The buffer started without any line, like an empty file, a single empty line
+    /// is then written as an empty file
+    empty_file: bool,
     dirty: bool,
+    pending: Option<char>,
+    pending_count: Option<usize>,
+    /// Count typed between an operator and its motion, e.g. the 3 of `2d3w`
+    motion_count: Option<usize>,
+    history: History,
+    /// Text yanked or deleted last
+    register: Option<RegisterContent>,
+    /// Registers `a` to `z`
+    named_registers: HashMap<char, RegisterContent>,
+    /// Register chosen with `"` for the next command
+    selected_register: Option<char>,
+    last_search: Option<String>,
+    /// Matches of `last_search` are highlighted until `:noh`
+    highlight_search: bool,
+    recorder: ChangeRecorder,
+    macros: Macros,
+    jumps: JumpList,
+    /// Positions saved with `m`
+    marks: HashMap<char, Position>,
+    keymap: Keymap,
+    /// Colors the lines, depends on the language of the file
+    highlighter: Option<Box<dyn Highlighter>>,
+    config: Config,
 }
 
-const STARTING_COL: usize = 4;
-
-impl Drop for State {
-    fn drop(&mut self) {
-        let mut lock = stdout().lock();
-        // Disable alt buffer
-        let _ = lock.write(b"\x1b[?1049l");
-        unsafe {
-            libc::tcsetattr(STDIN_FILENO, TCSANOW, &raw const self.previous_io_settings);
-        }
-    }
-}
+/// Minimum number of digits reserved for line numbers
+const MIN_NUMBER_WIDTH: usize = 3;
+/// Value of `target_col` making the cursor stick to the end of lines
+const END_OF_LINE: usize = usize::MAX;
 
 macro_rules! term_write {
     ($lock:expr, $($arg:tt)*) => {{
         use std::io::Write;
         write!($lock, $($arg)*)
-            .wrap_err("Could not write to stdout")
+            .wrap_err("Could not write to the frame")
     }};
 }
 
@@ -111,14 +180,38 @@ macro_rules! write_message {
     }};
 }
 
-fn get_window_size() -> Option<WindowSize> {
+/// Where the window size was read from
+#[derive(Debug, PartialEq, Eq)]
+enum WindowSizeSource {
+    Terminal,
+    Environment,
+    Default,
+}
+
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize { col: 80, row: 24 };
+
+/// Queries the terminal, then `$COLUMNS` and `$LINES`, and finally defaults to 80x24
+/// so that the editor can run without a controlling terminal
+fn get_window_size() -> (WindowSize, WindowSizeSource) {
+    if let Some(window_size) = terminal_window_size() {
+        return (window_size, WindowSizeSource::Terminal);
+    }
+
+    window_size_from_env(
+        std::env::var("COLUMNS").ok().as_deref(),
+        std::env::var("LINES").ok().as_deref(),
+    )
+}
+
+fn terminal_window_size() -> Option<WindowSize> {
     let mut window_size: libc::winsize;
     unsafe {
         window_size = std::mem::zeroed();
 
         for stream in [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO] {
             let error = libc::ioctl(stream, TIOCGWINSZ, &raw mut window_size);
-            if error == 0 {
+            // Some pseudo terminals report a size of 0
+            if error == 0 && window_size.ws_col != 0 && window_size.ws_row != 0 {
                 return Some(WindowSize {
                     col: window_size.ws_col as usize,
                     row: window_size.ws_row as usize,
@@ -130,6 +223,53 @@ fn get_window_size() -> Option<WindowSize> {
     None
 }
 
+/// Missing or invalid values are replaced by the default size
+fn window_size_from_env(
+    columns: Option<&str>,
+    lines: Option<&str>,
+) -> (WindowSize, WindowSizeSource) {
+    let parse = |value: Option<&str>| {
+        value
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|&n| n != 0)
+    };
+
+    match (parse(columns), parse(lines)) {
+        (None, None) => (DEFAULT_WINDOW_SIZE, WindowSizeSource::Default),
+        (col, row) => (
+            WindowSize {
+                col: col.unwrap_or(DEFAULT_WINDOW_SIZE.col),
+                row: row.unwrap_or(DEFAULT_WINDOW_SIZE.row),
+            },
+            WindowSizeSource::Environment,
+        ),
+    }
+}
+
+/// Set by the SIGWINCH handler, the window size must be queried again
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+fn install_sigwinch_handler() -> color_eyre::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sigwinch as *const () as libc::sighandler_t;
+        libc::sigemptyset(&raw mut action.sa_mask);
+
+        cvt(libc::sigaction(
+            libc::SIGWINCH,
+            &raw const action,
+            std::ptr::null_mut(),
+        ))
+        .wrap_err("Could not install SIGWINCH handler")?;
+    }
+
+    Ok(())
+}
+
 impl MessageType {
     const fn ansi_style(&self) -> &str {
         match self {
@@ -140,6 +280,30 @@ impl MessageType {
     }
 }
 
+impl Mode {
+    const fn name(&self) -> &str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insertion { .. } => "INSERT",
+            Mode::Command => "COMMAND",
+            Mode::Search { .. } => "SEARCH",
+            Mode::Visual {
+                linewise: false, ..
+            } => "VISUAL",
+            Mode::Visual { linewise: true, .. } => "VISUAL LINE",
+        }
+    }
+
+    /// Character displayed before the command buffer
+    const fn prompt(&self) -> Option<char> {
+        match self {
+            Mode::Command => Some(':'),
+            Mode::Search { .. } => Some('/'),
+            _ => None,
+        }
+    }
+}
+
 impl Message {
     const fn has_message(&self) -> bool {
         !self.msg.is_empty()
@@ -151,90 +315,860 @@ impl Message {
 }
 
 impl State {
+    /// The buffer always contains at least one line, an empty `text_lines` gets an empty line
+    fn new(mut text_lines: Vec<Line>, save_file: Option<PathBuf>, window_size: WindowSize) -> Self {
+        let empty_file = text_lines.is_empty();
+        if empty_file {
+            text_lines.push(Line::new());
+        }
+        let highlighter = save_file.as_deref().and_then(highlight::for_path);
+
+        State {
+            window_size,
+            cursor_pos: WindowSize { col: 0, row: 0 },
+            target_col: 0,
+            display_col: None,
+            text_lines,
+            text_offset: 0,
+            col_offset: 0,
+            previous_rows: Vec::new(),
+            current_mode: Mode::Normal,
+            command_buf: String::new(),
+            message: Message {
+                msg: String::new(),
+                r#type: MessageType::Info,
+            },
+            save_file,
+            end_of_line: true,
+            empty_file,
+            dirty: false,
+            pending: None,
+            pending_count: None,
+            motion_count: None,
+            history: History::default(),
+            register: None,
+            named_registers: HashMap::new(),
+            selected_register: None,
+            last_search: None,
+            highlight_search: false,
+            recorder: ChangeRecorder::default(),
+            macros: Macros::default(),
+            jumps: JumpList::default(),
+            marks: HashMap::new(),
+            keymap: Keymap::default(),
+            highlighter,
+            config: Config::default(),
+        }
+    }
+
+    /// Replaces the buffer with the content of a newly opened file
+    fn open_buffer(&mut self, content: FileContent, save_file: PathBuf) {
+        let mut text_lines = content.lines;
+        self.empty_file = text_lines.is_empty();
+        if self.empty_file {
+            text_lines.push(Line::new());
+        }
+
+        self.text_lines = text_lines;
+        self.save_file = Some(save_file);
+        self.end_of_line = content.end_of_line;
+        self.cursor_pos = WindowSize { col: 0, row: 0 };
+        self.target_col = 0;
+        self.text_offset = 0;
+        self.col_offset = 0;
+        self.dirty = false;
+        self.history = History::default();
+        self.marks.clear();
+        self.highlighter = self.save_file.as_deref().and_then(highlight::for_path);
+    }
+
+    /// Index in `text_lines` of the line under the cursor
+    const fn current_line_index(&self) -> usize {
+        self.cursor_pos.row + self.text_offset
+    }
+
+    /// Number of screen rows used to display text
+    /// Always at least 1 so that tiny windows don't break the cursor logic
+    fn text_rows(&self) -> usize {
+        self.window_size.row.saturating_sub(2).max(1)
+    }
+
+    /// Number of screen columns used to display text, right of the gutter
+    fn text_cols(&self) -> usize {
+        self.window_size
+            .col
+            .saturating_sub(self.gutter_width())
+            .max(1)
+    }
+
+    /// Adjusts `col_offset` so that the cursor is visible
+    /// Wrapped lines are always shown from their first column
+    fn scroll_horizontally(&mut self, text_cols: usize) {
+        if self.config.wrap {
+            self.col_offset = 0;
+            return;
+        }
+
+        let cursor_col = self.cursor_display_col();
+        if cursor_col < self.col_offset {
+            self.col_offset = cursor_col;
+        } else if cursor_col >= self.col_offset + text_cols {
+            self.col_offset = cursor_col + 1 - text_cols;
+        }
+    }
+
+    fn resize(&mut self, window_size: WindowSize) {
+        let current = self.current_line_index();
+        self.window_size = window_size;
+        // The terminal may have reflowed the screen
+        self.previous_rows.clear();
+        self.move_to_line(current);
+    }
+
     fn get_current_line(&self) -> Option<&Line> {
-        self.text_lines.get(self.cursor_pos.row + self.text_offset)
+        self.text_lines.get(self.current_line_index())
     }
 
     fn get_current_line_mut(&mut self) -> Option<&mut Line> {
-        self.text_lines
-            .get_mut(self.cursor_pos.row + self.text_offset)
+        let index = self.current_line_index();
+        self.text_lines.get_mut(index)
     }
 
-    fn init_ui(&mut self) -> color_eyre::Result<()> {
-        let mut lock = stdout().lock();
-        // Enable alt buffer
-        term_write!(&mut lock, "\x1b[?1049h")?;
+    /// Moves the cursor to the line at `index`, scrolling only if it is out of view
+    fn move_to_line(&mut self, index: usize) {
+        let index = index.min(self.text_lines.len().saturating_sub(1));
+        let text_rows = self.text_rows();
+
+        if index < self.text_offset {
+            self.text_offset = index;
+        } else if index >= self.text_offset + text_rows {
+            self.text_offset = index + 1 - text_rows;
+        }
+        if self.config.wrap {
+            // Every row of the cursor line must fit below the lines above it
+            let text_cols = self.text_cols();
+            let mut used: usize = (self.text_offset..=index)
+                .map(|i| self.wrapped_rows(i, text_cols))
+                .sum();
+            while used > text_rows && self.text_offset < index {
+                used -= self.wrapped_rows(self.text_offset, text_cols);
+                self.text_offset += 1;
+            }
+        }
+        self.cursor_pos.row = index - self.text_offset;
+    }
+
+    /// Display columns where the screen rows of the line at `index` start when wrapping
+    /// Room is kept after the last character for the `list` line end and the insertion cursor
+    fn wrap_starts(&self, index: usize, text_cols: usize) -> Vec<usize> {
+        let tab_width = self.config.tab_width;
+        match (&self.current_mode, self.text_lines.get(index)) {
+            (Mode::Insertion { buffer }, Some(_)) if index == self.current_line_index() => {
+                wrap_starts(buffer.chars(), 1, text_cols, tab_width)
+            }
+            (_, Some(line)) => wrap_starts(
+                line.chars(),
+                usize::from(self.config.list),
+                text_cols,
+                tab_width,
+            ),
+            (_, None) => vec![0],
+        }
+    }
 
-        self.draw_ui()
+    /// Number of screen rows taken by the line at `index` when wrapping
+    fn wrapped_rows(&self, index: usize, text_cols: usize) -> usize {
+        self.wrap_starts(index, text_cols).len()
     }
 
-    fn draw_ui(&mut self) -> color_eyre::Result<()> {
-        let mut lock = stdout().lock();
-        // Clear screen, move cursor to 0,0
-        term_write!(&mut lock, "\x1b[2J\x1b[H")?;
+    /// Number of lines shown on the screen, at least 1
+    fn visible_lines(&self) -> usize {
+        let remaining = self.text_lines.len() - self.text_offset;
+        if !self.config.wrap {
+            return self.text_rows().min(remaining);
+        }
+
+        let text_cols = self.text_cols();
+        let mut used = 0;
+        let fitting = (self.text_offset..self.text_lines.len())
+            .take_while(|&i| {
+                used += self.wrapped_rows(i, text_cols);
+                used <= self.text_rows()
+            })
+            .count();
+        fitting.max(1)
+    }
+
+    /// Returns true if the cursor moved
+    /// The cursor moves by grapheme clusters so it never lands inside a composed character
+    fn move_left(&mut self) -> bool {
+        if self.cursor_pos.col == 0 {
+            return false;
+        }
+        self.cursor_pos.col = self
+            .get_current_line()
+            .map_or(self.cursor_pos.col - 1, |line| {
+                line.prev_grapheme(self.cursor_pos.col)
+            });
+        self.target_col = self.cursor_pos.col;
+        true
+    }
+
+    /// Returns true if the cursor moved
+    fn move_right(&mut self) -> bool {
+        let Some(line) = self.get_current_line() else {
+            return false;
+        };
+        if self.cursor_pos.col >= line.len() {
+            return false;
+        }
+        self.cursor_pos.col = line.next_grapheme(self.cursor_pos.col);
+        self.target_col = self.cursor_pos.col;
+        true
+    }
+
+    /// Returns true if the cursor moved
+    fn move_down(&mut self) -> bool {
+        let current = self.current_line_index();
+        if current + 1 >= self.text_lines.len() {
+            return false;
+        }
+        self.move_to_line(current + 1);
+        self.clamp_col_to_current_line();
+        true
+    }
+
+    /// Returns true if the cursor moved
+    fn move_up(&mut self) -> bool {
+        let current = self.current_line_index();
+        if current == 0 {
+            return false;
+        }
+        self.move_to_line(current - 1);
+        self.clamp_col_to_current_line();
+        true
+    }
+
+    /// Returns true if the cursor moved
+    /// Moves to the next or previous screen row of wrapped text at the same screen column,
+    /// like `gj` and `gk`, without wrapping every line is one row
+    fn move_display_row(&mut self, down: bool) -> bool {
+        if !self.config.wrap {
+            return if down {
+                self.move_down()
+            } else {
+                self.move_up()
+            };
+        }
+
+        let text_cols = self.text_cols();
+        let tab_width = self.config.tab_width;
+        let current = self.current_line_index();
+        // The room kept for the `list` line end is not reachable
+        let starts = |line: &Line| wrap_starts(line.chars(), 0, text_cols, tab_width);
+        let Some(line) = self.get_current_line() else {
+            return false;
+        };
+        let width = line.get_unicode_width_at(self.cursor_pos.col, tab_width);
+        let current_starts = starts(line);
+        let row = current_starts
+            .iter()
+            .rposition(|&start| start <= width)
+            .unwrap_or(0);
+        let col = match self.display_col {
+            Some((col, position)) if position == (current, self.cursor_pos.col) => col,
+            _ => width - current_starts[row],
+        };
+
+        let (index, row) = if down && row + 1 < current_starts.len() {
+            (current, row + 1)
+        } else if down && current + 1 < self.text_lines.len() {
+            (current + 1, 0)
+        } else if !down && row > 0 {
+            (current, row - 1)
+        } else if !down && current > 0 {
+            (current - 1, starts(&self.text_lines[current - 1]).len() - 1)
+        } else {
+            return false;
+        };
+
+        // The character covering the screen column, or the last one of a shorter row
+        let line = &self.text_lines[index];
+        let target_starts = starts(line);
+        let mut target = target_starts[row] + col;
+        if let Some(&next) = target_starts.get(row + 1) {
+            target = target.min(next - 1);
+        }
+        let mut width = 0;
+        let target_col = line
+            .chars()
+            .position(|c| {
+                width += char_width(c, width, tab_width);
+                width > target
+            })
+            .unwrap_or(line.len());
+
+        let last_col = line.len().saturating_sub(1);
+        self.move_to_line(index);
+        self.cursor_pos.col = target_col.min(last_col);
+        self.target_col = self.cursor_pos.col;
+        self.display_col = Some((col, (index, self.cursor_pos.col)));
+        true
+    }
+
+    /// Runs `motion` `count` times, stopping early once the cursor cannot move
+    fn repeat_motion(&mut self, count: Option<usize>, motion: fn(&mut Self) -> bool) {
+        for _ in 0..count.unwrap_or(1) {
+            if !motion(self) {
+                break;
+            }
+        }
+    }
+
+    /// Scrolls the text and the cursor by half a screen, like Ctrl-D and Ctrl-U
+    fn scroll_half_page(&mut self, down: bool) {
+        let text_rows = self.text_rows();
+        let amount = (text_rows / 2).max(1);
+        let current = self.current_line_index();
+
+        if down {
+            let max_offset = self.text_lines.len().saturating_sub(text_rows);
+            self.text_offset = (self.text_offset + amount).min(max_offset);
+            self.move_to_line(current + amount);
+        } else {
+            self.text_offset = self.text_offset.saturating_sub(amount);
+            self.move_to_line(current.saturating_sub(amount));
+        }
+        self.clamp_col_to_current_line();
+    }
+
+    /// Scrolls by a screen minus two lines, like Ctrl-F and Ctrl-B
+    /// The cursor goes to the first line of the screen when scrolling down and to the last one
+    /// when scrolling up, or to the end of the buffer when there is nothing left to scroll
+    fn scroll_page(&mut self, down: bool) {
+        let text_rows = self.text_rows();
+        let amount = text_rows.saturating_sub(2).max(1);
+        let last = self.text_lines.len() - 1;
+
+        if down {
+            let max_offset = self.text_lines.len().saturating_sub(text_rows);
+            if self.text_offset + amount > max_offset {
+                self.text_offset = max_offset;
+                self.move_to_line(last);
+            } else {
+                self.text_offset += amount;
+                self.move_to_line(self.text_offset);
+            }
+        } else if self.text_offset == 0 {
+            self.move_to_line(0);
+        } else {
+            self.text_offset = self.text_offset.saturating_sub(amount);
+            self.move_to_line(self.text_offset + self.visible_lines() - 1);
+        }
+        self.clamp_col_to_current_line();
+    }
+
+    /// Scrolls so that `rows_above` screen rows are left above the cursor line, like `zt`,
+    /// `zz` and `zb`, without scrolling past the start or end of the buffer
+    /// The cursor stays on its line
+    fn scroll_cursor_line_to(&mut self, rows_above: usize) {
+        let current = self.current_line_index();
+        let max_offset = self.first_line_fitting(self.text_lines.len(), self.text_rows());
+        self.text_offset = self.first_line_fitting(current, rows_above).min(max_offset);
+        self.move_to_line(current);
+    }
+
+    /// Index of the first of the lines before `end` that fit together in `rows` screen rows
+    fn first_line_fitting(&self, end: usize, rows: usize) -> usize {
+        if !self.config.wrap {
+            return end.saturating_sub(rows);
+        }
+
+        let text_cols = self.text_cols();
+        let mut used = 0;
+        (0..end)
+            .rev()
+            .take_while(|&i| {
+                used += self.wrapped_rows(i, text_cols);
+                used <= rows
+            })
+            .last()
+            .unwrap_or(end)
+    }
+
+    /// Returns true if the cursor moved
+    fn move_word_forward(&mut self) -> bool {
+        let current = self.current_line_index();
+        if let Some(col) = self.text_lines[current].next_word_start(self.cursor_pos.col) {
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        // An empty line counts as a word
+        let next_word = self.text_lines[current + 1..]
+            .iter()
+            .enumerate()
+            .find_map(|(i, line)| match line.chars().next() {
+                None => Some((i, 0)),
+                Some(c) if !c.is_whitespace() => Some((i, 0)),
+                Some(_) => line.next_word_start(0).map(|col| (i, col)),
+            });
+
+        if let Some((i, col)) = next_word {
+            self.move_to_line(current + 1 + i);
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns true if the cursor moved
+    fn move_word_end(&mut self) -> bool {
+        let current = self.current_line_index();
+        // Always move at least one character, so `e` on the end of a word goes to the next one
+        let line = &self.text_lines[current];
+        if let Some(col) = line.word_end(line.next_grapheme(self.cursor_pos.col)) {
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        // Unlike `w`, empty lines are skipped
+        let word_end = self.text_lines[current + 1..]
+            .iter()
+            .enumerate()
+            .find_map(|(i, line)| line.word_end(0).map(|col| (i, col)));
+
+        if let Some((i, col)) = word_end {
+            self.move_to_line(current + 1 + i);
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns true if the cursor moved
+    fn move_word_backward(&mut self) -> bool {
+        let current = self.current_line_index();
+        if let Some(col) = self.text_lines[current].prev_word_start(self.cursor_pos.col) {
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        // An empty line counts as a word
+        let prev_word = self.text_lines[..current]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, line)| {
+                if line.is_empty() {
+                    Some((i, 0))
+                } else {
+                    line.prev_word_start(line.len()).map(|col| (i, col))
+                }
+            });
+
+        if let Some((i, col)) = prev_word {
+            self.move_to_line(i);
+            self.cursor_pos.col = col;
+            self.target_col = col;
+            return true;
+        }
+
+        false
+    }
+
+    /// Number of columns before the text, including the space after line numbers
+    fn gutter_width(&self) -> usize {
+        let widest = match (
+            self.config.show_line_numbers,
+            self.config.relative_line_numbers,
+        ) {
+            (false, false) => return 0,
+            (true, false) => self.text_lines.len(),
+            (show_line_numbers, true) => {
+                let last_row = self
+                    .text_rows()
+                    .min(self.text_lines.len() - self.text_offset)
+                    - 1;
+                let distance = self.cursor_pos.row.max(last_row - self.cursor_pos.row);
+                if show_line_numbers {
+                    distance.max(self.current_line_index() + 1)
+                } else {
+                    distance
+                }
+            }
+        };
+
+        let digits = widest.checked_ilog10().unwrap_or(0) as usize + 1;
+        digits.max(MIN_NUMBER_WIDTH) + 1
+    }
 
-        for n_line in 0..self.window_size.row - 2 {
-            if n_line < self.text_lines.len() {
-                term_write!(&mut lock, "{:>3} ", n_line + 1 + self.text_offset)?;
+    /// Number displayed in the gutter for the screen row `n_line`
+    fn line_number(&self, n_line: usize) -> usize {
+        if !self.config.relative_line_numbers {
+            return n_line + 1 + self.text_offset;
+        }
+
+        if n_line == self.cursor_pos.row {
+            // Hybrid mode shows the absolute number on the cursor line
+            if self.config.show_line_numbers {
+                self.current_line_index() + 1
             } else {
-                term_write!(&mut lock, "~   ")?;
+                0
             }
+        } else {
+            n_line.abs_diff(self.cursor_pos.row)
+        }
+    }
+
+    /// Display column of the cursor in the current line
+    fn cursor_display_col(&self) -> usize {
+        if let Mode::Insertion { buffer } = &self.current_mode {
+            buffer.cursor_width()
+        } else if let Some(line) = self.get_current_line() {
+            line.get_unicode_width_at(self.cursor_pos.col, self.config.tab_width)
+        } else {
+            self.cursor_pos.col
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.save_file
+            .as_ref()
+            .map_or_else(|| "[No Name]".to_owned(), |p| p.display().to_string())
+    }
+
+    /// Mode, file name and modified flag on the left, cursor position on the right
+    fn status_line(&self) -> String {
+        let name = self.file_name();
+        let modified = if self.dirty { " [+]" } else { "" };
+        let recording = self
+            .recording_macro()
+            .map_or_else(String::new, |name| format!(" | recording @{name}"));
+        let left = format!(
+            " {} | {name}{modified}{recording}",
+            self.current_mode.name()
+        );
+        let right = format!(
+            "{}:{} ",
+            self.current_line_index() + 1,
+            self.cursor_display_col() + 1
+        );
+
+        let padding = self
+            .window_size
+            .col
+            .saturating_sub(UnicodeWidthStr::width(left.as_str()) + right.len());
+        format!("{left}{:padding$}{right}", "")
+    }
+
+    /// File name, modified flag, number of lines and how far the cursor is, like Ctrl-G
+    fn file_info(&self) -> String {
+        let name = self.file_name();
+        let modified = if self.dirty { " [Modified]" } else { "" };
+        let count = self.text_lines.len();
+        let plural = if count == 1 { "" } else { "s" };
+        let percent = (self.current_line_index() + 1) * 100 / count;
+
+        format!("\"{name}\"{modified} {count} line{plural} --{percent}%--")
+    }
 
-            let is_cursor_line = n_line == self.cursor_pos.row;
+    /// Writes the part of `chars` visible between the display columns `left` and `right`,
+    /// characters in `highlights` get their background and `spans` are colored
+    /// Returns the display width of the characters
+    fn draw_chars(
+        &self,
+        frame: &mut Vec<u8>,
+        chars: impl Iterator<Item = char>,
+        (left, right): (usize, usize),
+        highlights: &[(RangeInclusive<usize>, Rgb)],
+        spans: &[Span],
+        line_background: &str,
+    ) -> color_eyre::Result<usize> {
+        // A wide character cut by an edge is replaced by spaces
+        let mut col = 0;
+        let mut highlighted = None;
+        let mut spans = spans.iter().peekable();
+        let mut color = None;
+        for (i, c) in chars.enumerate() {
+            while spans.next_if(|span| span.end <= i).is_some() {}
+            let kind = spans
+                .peek()
+                .filter(|span| span.start <= i)
+                .map(|span| span.kind);
+
+            let width = char_width(c, col, self.config.tab_width);
+            let (start, end) = (col, col + width);
+            col = end;
+            if end <= left {
+                continue;
+            }
+            if start >= right {
+                break;
+            }
 
-            if is_cursor_line {
-                // Set highlight color
-                term_write!(&mut lock, "\x1b[48;2;54;58;79m")?;
+            let highlight = highlights
+                .iter()
+                .find(|(range, _)| range.contains(&i))
+                .map(|&(_, color)| color);
+            if highlight != highlighted {
+                highlighted = highlight;
+                if let Some(color) = highlight {
+                    term_write!(frame, "{}", Background(color))?;
+                } else {
+                    // Go back to the line's background
+                    term_write!(frame, "{line_background}")?;
+                }
+            }
+            if kind != color {
+                color = kind;
+                match kind {
+                    Some(kind) => {
+                        let foreground = Foreground(self.config.theme.token_foreground(kind));
+                        term_write!(frame, "{foreground}")?;
+                    }
+                    // Default foreground
+                    None => term_write!(frame, "\x1b[39m")?,
+                }
             }
 
-            if is_cursor_line && let Mode::Insertion { buffer } = &self.current_mode {
-                for c in buffer.start.iter().chain(&buffer.end) {
-                    term_write!(&mut lock, "{c}")?;
+            if start < left || end > right {
+                let visible = end.min(right) - start.max(left);
+                term_write!(frame, "{:visible$}", "")?;
+            } else if c == '\t' {
+                if self.config.list {
+                    term_write!(frame, "»{:1$}", "", width - 1)?;
+                } else {
+                    term_write!(frame, "{:width$}", "")?;
                 }
+            } else if c == ' ' && self.config.list {
+                term_write!(frame, "·")?;
             } else {
-                term_write!(
-                    &mut lock,
-                    "{}",
-                    self.text_lines
-                        .get(n_line + self.text_offset)
-                        .map_or("", |line| line.as_str())
-                )?;
+                term_write!(frame, "{c}")?;
+            }
+        }
+        if color.is_some() {
+            term_write!(frame, "\x1b[39m")?;
+        }
+
+        Ok(col)
+    }
+
+    /// Colored parts of the line at `index`, the one being typed in included
+    fn line_spans(&self, index: usize) -> Vec<Span> {
+        let Some(highlighter) = self.highlighter.as_ref().filter(|_| self.config.syntax) else {
+            return Vec::new();
+        };
+
+        match &self.current_mode {
+            Mode::Insertion { buffer } if index == self.current_line_index() => {
+                highlighter.highlight(&buffer.to_line())
             }
+            _ => self
+                .text_lines
+                .get(index)
+                .map_or_else(Vec::new, |line| highlighter.highlight(line)),
+        }
+    }
+
+    /// Draws the display columns `left` to `right` of the line `n_line` of the screen,
+    /// gutter included
+    /// The gutter of the rows continuing a wrapped line is left blank
+    /// `search` is the pattern whose matches are highlighted
+    fn render_row(
+        &self,
+        n_line: usize,
+        (left, right): (usize, usize),
+        first_row: bool,
+        search: Option<&Pattern>,
+    ) -> color_eyre::Result<Vec<u8>> {
+        let mut row = Vec::with_capacity(self.window_size.col + 32);
+        let gutter_width = self.gutter_width();
+        if n_line + self.text_offset >= self.text_lines.len() {
+            term_write!(&mut row, "{:<gutter_width$}", "~")?;
+        } else if !first_row {
+            term_write!(&mut row, "{:gutter_width$}", "")?;
+        } else if gutter_width != 0 {
+            term_write!(
+                &mut row,
+                "{:>width$} ",
+                self.line_number(n_line),
+                width = gutter_width - 1
+            )?;
+        }
+
+        let is_cursor_line = n_line == self.cursor_pos.row;
+        let line_background = if is_cursor_line {
+            // Set highlight color
+            let background = Background(self.config.theme.cursor_line_background).to_string();
+            term_write!(&mut row, "{background}")?;
+            background
+        } else {
+            "\x1b[49m".to_owned()
+        };
+
+        let index = n_line + self.text_offset;
+        let theme = &self.config.theme;
+        let mut highlights: Vec<_> = search
+            .zip(self.text_lines.get(index))
+            .map_or_else(Vec::new, |(pattern, line)| pattern.highlights(line))
+            .into_iter()
+            .map(|range| (range, theme.search_background))
+            .collect();
+        if let Some((start, end)) = self
+            .selection()
+            .filter(|(start, end)| (start.0..=end.0).contains(&index))
+        {
+            let from = if index == start.0 { start.1 } else { 0 };
+            let to = if index == end.0 { end.1 } else { usize::MAX };
+            highlights.push((from..=to, theme.selection_background));
+        }
+        let spans = self.line_spans(index);
+        let mut width = None;
+        if is_cursor_line && let Mode::Insertion { buffer } = &self.current_mode {
+            let chars = buffer.chars();
+            width = Some(self.draw_chars(
+                &mut row,
+                chars,
+                (left, right),
+                &[],
+                &spans,
+                &line_background,
+            )?);
+        } else if let Some(line) = self.text_lines.get(index)
+            && highlights.is_empty()
+            && spans.is_empty()
+            && !line.has_tab()
+            && !self.config.list
+        {
+            // Most lines can be written in one go
+            let first = line.index_at_width(left);
+            // A wide character cut by the left edge
+            let padding = line
+                .get_unicode_width_at(first, self.config.tab_width)
+                .saturating_sub(left);
+            term_write!(
+                &mut row,
+                "{:padding$}{}",
+                "",
+                line.truncate_to_width(first, (right - left).saturating_sub(padding))
+            )?;
+        } else if let Some(line) = self.text_lines.get(index) {
+            let chars = line.chars();
+            width = Some(self.draw_chars(
+                &mut row,
+                chars,
+                (left, right),
+                &highlights,
+                &spans,
+                &line_background,
+            )?);
+        }
+
+        // With `list`, the end of the line is shown too
+        if self.config.list
+            && let Some(width) = width
+            && (left..right).contains(&width)
+        {
+            term_write!(&mut row, "{line_background}$")?;
+        }
+
+        // Erase in line, reset all modes
+        term_write!(&mut row, "\x1b[K\x1b[0m")?;
+
+        Ok(row)
+    }
 
-            // Erase in line, reset all modes, move cursor to beginning of next line
-            term_write!(&mut lock, "\x1b[K\x1b[0m\x1b[1E")?;
+    /// Builds the whole frame in memory so that it is written in one go,
+    /// the terminal never shows a half drawn screen
+    /// Only the text rows that changed since the previous frame are redrawn
+    fn render(&mut self) -> color_eyre::Result<Vec<u8>> {
+        let mut frame = Vec::with_capacity(self.window_size.col * self.window_size.row * 2);
+
+        if self.config.wrap {
+            // Typing can make the cursor line taller than the room left below it
+            self.move_to_line(self.current_line_index());
+        }
+        let gutter_width = self.gutter_width();
+        let text_cols = self.text_cols();
+        self.scroll_horizontally(text_cols);
+
+        // Compiled once for the whole frame
+        let search = self.search_highlight();
+        let rows = if self.config.wrap {
+            self.render_wrapped_rows(text_cols, search.as_ref())?
+        } else {
+            let (left, right) = (self.col_offset, self.col_offset + text_cols);
+            (0..self.text_rows())
+                .map(|n_line| self.render_row(n_line, (left, right), true, search.as_ref()))
+                .collect::<color_eyre::Result<Vec<_>>>()?
+        };
+        let full_redraw = rows.len() != self.previous_rows.len();
+        // Hide the cursor while drawing so that it does not jump around the screen
+        term_write!(&mut frame, "\x1b[?25l")?;
+        if full_redraw {
+            // Clear screen
+            term_write!(&mut frame, "\x1b[2J")?;
+        }
+        for (n_line, row) in rows.iter().enumerate() {
+            if full_redraw || self.previous_rows[n_line] != *row {
+                term_write!(&mut frame, "\x1b[{};1H", n_line + 1)?;
+                frame.extend_from_slice(row);
+            }
         }
+        self.previous_rows = rows;
 
-        // Set background color and erase it in line
+        let theme = &self.config.theme;
+        // Clear the message row, move to the status line
+        term_write!(
+            &mut frame,
+            "\x1b[{};1H\x1b[K\x1b[{};1H",
+            self.window_size.row,
+            self.text_rows() + 1
+        )?;
+        // Set colors and erase the background in line
         term_write!(
-            &mut lock,
-            "\x1b[48;2;30;32;48m This is the overlay\x1b[K\x1b[0m",
+            &mut frame,
+            "{}{}{}\x1b[K\x1b[0m",
+            Background(theme.status_line_background),
+            Foreground(theme.status_line_foreground),
+            self.status_line()
         )?;
 
-        if matches!(self.current_mode, Mode::Command) {
+        if let Some(prompt) = self.current_mode.prompt() {
             write_message!(
-                &mut lock,
+                &mut frame,
                 self.window_size.row,
-                ":{}\x1b[25m",
+                "{prompt}{}",
                 self.command_buf
             )?;
         } else {
-            let columns = if let Mode::Insertion { buffer } = &self.current_mode {
+            let columns = if let Mode::Insertion { .. } = &self.current_mode {
                 write_message!(
-                    &mut lock,
+                    &mut frame,
                     self.window_size.row,
                     "\x1b[1m-- INSERT --\x1b[22m"
                 )?;
 
-                buffer
-                    .start
-                    .iter()
-                    .map(|&c| UnicodeWidthChar::width(c).unwrap_or(0))
-                    .sum()
+                self.cursor_display_col()
             } else {
-                if self.message.has_message() {
+                if matches!(self.current_mode, Mode::Visual { .. }) {
                     write_message!(
-                        &mut lock,
+                        &mut frame,
+                        self.window_size.row,
+                        "\x1b[1m-- {} --\x1b[22m",
+                        self.current_mode.name()
+                    )?;
+                } else if self.message.has_message() {
+                    write_message!(
+                        &mut frame,
                         self.window_size.row,
                         "{}{}\x1b[0m",
                         self.message.r#type.ansi_style(),
@@ -242,251 +1176,711 @@ impl State {
                     )?;
                 }
 
-                if let Some(line) = self.get_current_line() {
-                    line.get_unicode_width_at(self.cursor_pos.col)
-                } else {
-                    self.cursor_pos.col
-                }
+                self.cursor_display_col()
+            };
+
+            let (row, col) = if self.config.wrap {
+                let rows_above: usize = (0..self.cursor_pos.row)
+                    .map(|n_line| self.wrapped_rows(n_line + self.text_offset, text_cols))
+                    .sum();
+                let starts = self.wrap_starts(self.current_line_index(), text_cols);
+                let segment = starts
+                    .iter()
+                    .rposition(|&start| start <= columns)
+                    .unwrap_or(0);
+                let row = rows_above + segment;
+                (row.min(self.text_rows() - 1), columns - starts[segment])
+            } else {
+                (self.cursor_pos.row, columns - self.col_offset)
             };
 
-            // Move cursor to its position, set blinking mode
+            // Move cursor to its position
             // NB: apparently the escape code used to position the cursor
             // is 1 indexed so we need to add 1
-            term_write!(
-                &mut lock,
-                "\x1b[{};{}H\x1b[25m",
-                self.cursor_pos.row + 1,
-                columns + STARTING_COL + 1
-            )?;
+            term_write!(&mut frame, "\x1b[{};{}H", row + 1, col + gutter_width + 1)?;
+        }
+        let cursor_shape = if let Mode::Insertion { .. } = self.current_mode {
+            self.config.insert_cursor
+        } else {
+            self.config.normal_cursor
+        };
+        term_write!(&mut frame, "{cursor_shape}\x1b[?25h")?;
+
+        Ok(frame)
+    }
+
+    /// Draws the lines from `text_offset` over as many screen rows as they need
+    /// A line that does not fit at the bottom of the screen is cut
+    fn render_wrapped_rows(
+        &self,
+        text_cols: usize,
+        search: Option<&Pattern>,
+    ) -> color_eyre::Result<Vec<Vec<u8>>> {
+        let text_rows = self.text_rows();
+        let mut rows = Vec::with_capacity(text_rows);
+        let mut n_line = 0;
+        while rows.len() < text_rows {
+            let starts = self.wrap_starts(n_line + self.text_offset, text_cols);
+            for (segment, &left) in starts.iter().enumerate().take(text_rows - rows.len()) {
+                let right = starts.get(segment + 1).copied().unwrap_or(left + text_cols);
+                rows.push(self.render_row(n_line, (left, right), segment == 0, search)?);
+            }
+            n_line += 1;
         }
 
+        Ok(rows)
+    }
+
+    fn draw_ui(&mut self) -> color_eyre::Result<()> {
+        let frame = self.render()?;
+
+        let mut lock = stdout().lock();
+        lock.write_all(&frame)
+            .wrap_err("Could not write to stdout")?;
         flush(&mut lock)
     }
 
+    /// Puts the cursor on the line at `target_col`, or on the last character if it is shorter
+    /// The cursor goes back to the start of a composed character
     fn clamp_col_to_current_line(&mut self) {
-        let len = self.get_current_line().map_or(0, |l| l.len());
-        self.cursor_pos.col = self.target_col.min(len);
+        self.cursor_pos.col = self.get_current_line().map_or(0, |line| {
+            line.grapheme_start(self.target_col.min(line.len().saturating_sub(1)))
+        });
     }
 
     fn enable_insertion_mode(&mut self) {
         if let Some(line) = self.get_current_line()
             && self.cursor_pos.col <= line.len()
         {
-            let mut split_buffer = SplitBuffer {
-                // Those are completely arbitrary values
-                // This may need more extensive testing to find better ones
-                start: Vec::with_capacity(30.max(self.cursor_pos.col * 3).max(line.len() * 2)),
-                end: line.chars().skip(self.cursor_pos.col).collect(),
-            };
-            split_buffer
-                .start
-                .extend(line.chars().take(self.cursor_pos.col));
-
+            let buffer = GapBuffer::new(line, self.cursor_pos.col, self.config.tab_width);
             self.message.clear();
-            self.current_mode = Mode::Insertion {
-                buffer: split_buffer,
-            };
+            self.current_mode = Mode::Insertion { buffer };
         }
     }
 
-    fn add_new_line(&mut self) {
+    /// Inserts an empty line at `index` and moves the cursor to it
+    fn add_new_line(&mut self, index: usize) {
         // This should not allocate yet so this is good
-        // It is assumed the cursor cannot be out of bounds
-        // This assumption is only true if I know how to code correctly
-        self.text_lines
-            .insert(self.cursor_pos.row + self.text_offset, Line::new());
+        self.text_lines.insert(index, Line::new());
+        self.adjust_marks(index, 0, 1);
+        self.move_to_line(index);
         self.cursor_pos.col = 0;
         self.dirty = true;
     }
 
+    /// Inserts a line at `index` for `o` and `O` and starts typing in it
+    fn open_line(&mut self, index: usize) {
+        let indent = match self.get_current_line() {
+            Some(line) if self.config.auto_indent => Line::from(line.leading_whitespace()),
+            _ => Line::new(),
+        };
+
+        self.add_new_line(index);
+        self.cursor_pos.col = indent.len();
+        self.text_lines[index] = indent;
+        self.target_col = self.cursor_pos.col;
+        self.enable_insertion_mode();
+    }
+
     /// Returns true if the program should continue
-    fn handle_keypress_normal(&mut self, key: &Key) -> bool {
-        match key {
-            Key::ArrowLeft | Key::Char(b'h') | Key::Backspace => {
-                if self.cursor_pos.col == 0 {
-                    return true;
-                }
-                self.cursor_pos.col -= 1;
-                self.target_col = self.cursor_pos.col;
-            }
-            Key::ArrowRight | Key::Char(b'l') => {
-                if let Some(line) = self.get_current_line()
-                    && self.cursor_pos.col >= line.len()
-                {
-                    return true;
-                }
-                self.cursor_pos.col += 1;
-                self.target_col = self.cursor_pos.col;
-            }
-            Key::ArrowDown | Key::Char(b'j') | Key::Enter => {
-                if self.cursor_pos.row + self.text_offset >= self.text_lines.len() - 1 {
-                    return true;
-                }
-                if self.cursor_pos.row >= self.window_size.row - 3 {
-                    self.text_offset += 1;
-                } else {
-                    self.cursor_pos.row += 1;
+    fn handle_keypress(&mut self, key: &Key) -> bool {
+        self.record_macro_key(key);
+        self.record_key(key);
+        let current_mode = std::mem::replace(&mut self.current_mode, Mode::Normal);
+
+        // Maybe there is a way to put the handle method in the enum?
+        let continues = match current_mode {
+            Mode::Normal => {
+                let selecting_register = self.pending == Some('"');
+                let continues = self.handle_keypress_normal(key);
+                // The register only applies to the command typed right after it
+                if !selecting_register && self.pending.is_none() && self.pending_count.is_none() {
+                    self.selected_register = None;
                 }
-                self.clamp_col_to_current_line();
+                continues
             }
-            Key::ArrowUp | Key::Char(b'k') => {
-                if self.cursor_pos.row == 0 {
-                    if self.text_offset == 0 {
-                        return true;
-                    }
+            Mode::Insertion { buffer } => self.handle_keypress_insertion(key, buffer),
+            Mode::Command => self.handle_keypress_command(key),
+            Mode::Search { origin, view } => self.handle_keypress_search(key, origin, view),
+            Mode::Visual { anchor, linewise } => self.handle_keypress_visual(key, anchor, linewise),
+        };
+        self.finish_recording();
+
+        continues
+    }
 
-                    self.text_offset -= 1;
-                } else {
-                    self.cursor_pos.row -= 1;
-                }
-                self.clamp_col_to_current_line();
-            }
-            // TODO: change this to gg
-            Key::Char(b'g') => {
-                self.text_offset = 0;
-                self.cursor_pos.row = 0;
-                self.cursor_pos.col = 0;
+    /// Removes `count` lines starting with the one under the cursor and saves them in the register
+    fn delete_lines(&mut self, count: usize) {
+        self.save_undo_point();
+
+        let current = self.current_line_index();
+        let end = current.saturating_add(count).min(self.text_lines.len());
+        let lines = self.text_lines.drain(current..end).collect();
+        self.adjust_marks(current, end - current, 0);
+        self.set_register(RegisterContent::Lines(lines));
+        if self.text_lines.is_empty() {
+            self.text_lines.push(Line::new());
+        }
+
+        self.dirty = true;
+        self.move_to_line(current);
+        self.clamp_col_to_current_line();
+    }
+
+    /// Appends the next line to the current one, separated by a single space
+    /// Leading whitespace of the next line is removed
+    fn join_with_next_line(&mut self) {
+        let current = self.current_line_index();
+        if current + 1 >= self.text_lines.len() {
+            return;
+        }
+        self.save_undo_point();
+
+        let next = self.text_lines.remove(current + 1);
+        let line = &mut self.text_lines[current];
+        let join_col = line.len();
+        let rest = next.as_str().trim_start();
+        if !line.is_empty() && !rest.is_empty() {
+            line.push(' ');
+        }
+        let rest_col = line.len();
+        line.extend(rest.chars());
+
+        self.dirty = true;
+        self.cursor_pos.col = join_col.min(line.len().saturating_sub(1));
+        self.target_col = self.cursor_pos.col;
+        self.join_marks(current + 1, current, rest_col);
+    }
+
+    /// Swaps the case of `count` characters from the cursor and moves after them
+    fn toggle_case(&mut self, count: usize) {
+        let index = self.current_line_index();
+        let mut col = self.cursor_pos.col;
+        if col >= self.text_lines[index].len() {
+            return;
+        }
+        self.save_undo_point();
+
+        let line = &mut self.text_lines[index];
+        for _ in 0..count {
+            match line.toggle_case_at(col) {
+                Some(len) => col += len,
+                None => break,
             }
-            Key::Char(b'G') => {
-                if self.text_lines.len() > self.window_size.row - 2 {
-                    self.text_offset = self.text_lines.len() - self.window_size.row + 2;
-                    self.cursor_pos.row = self.window_size.row - 3;
-                } else {
-                    self.cursor_pos.row = self.text_lines.len() - 1;
-                }
+        }
+
+        self.dirty = true;
+        self.cursor_pos.col = col.min(line.len() - 1);
+        self.target_col = self.cursor_pos.col;
+    }
+
+    /// Replaces the character under the cursor, does nothing on an empty line
+    /// Combining characters are replaced with their base
+    fn replace_char_under_cursor(&mut self, ch: char) {
+        let col = self.cursor_pos.col;
+        if self.get_current_line().is_some_and(|line| col < line.len()) {
+            self.save_undo_point();
+            let index = self.current_line_index();
+            let line = &mut self.text_lines[index];
+            for _ in col + 1..line.next_grapheme(col) {
+                line.remove_at(col + 1);
+            }
+            line.replace_at(col, ch);
+            self.dirty = true;
+        }
+    }
+
+    /// Adds `key` to the pending count if it is a digit
+    /// Returns true if the key was consumed
+    fn accumulate_count(&mut self, key: &Key) -> bool {
+        if let Key::Char(c @ '0'..='9') = key
+            && (*c != '0' || self.pending_count.is_some())
+        {
+            let digit = c.to_digit(10).unwrap_or_default() as usize;
+            let count = self.pending_count.unwrap_or(0);
+            self.pending_count = Some(count.saturating_mul(10).saturating_add(digit));
+            return true;
+        }
+
+        false
+    }
+
+    /// Moves the cursor if `action` is a motion shared by Normal and Visual modes
+    /// Returns true if the action was a motion
+    fn handle_motion(&mut self, action: Action, count: Option<usize>) -> bool {
+        match action {
+            Action::Left => {
+                self.repeat_motion(count, Self::move_left);
+            }
+            Action::Right => {
+                self.repeat_motion(count, Self::move_right);
+            }
+            Action::Down => {
+                self.repeat_motion(count, Self::move_down);
+            }
+            Action::Up => {
+                self.repeat_motion(count, Self::move_up);
+            }
+            Action::DisplayDown => {
+                self.repeat_motion(count, |state| state.move_display_row(true));
+            }
+            Action::DisplayUp => {
+                self.repeat_motion(count, |state| state.move_display_row(false));
+            }
+            Action::WordForward => {
+                self.repeat_motion(count, Self::move_word_forward);
+            }
+            Action::WordBackward => {
+                self.repeat_motion(count, Self::move_word_backward);
+            }
+            Action::WordEnd => {
+                self.repeat_motion(count, Self::move_word_end);
+            }
+            Action::LineStart => {
                 self.cursor_pos.col = 0;
+                self.target_col = 0;
             }
-            // TODO: change this to dd
-            Key::Char(b'd') => {
-                if let Some(line) = self.get_current_line_mut() {
-                    line.clear();
-                    let lines_below =
-                        &mut self.text_lines[self.cursor_pos.row + self.text_offset..];
-                    lines_below.rotate_left(1);
+            Action::LineEnd => {
+                self.target_col = END_OF_LINE;
+                self.clamp_col_to_current_line();
+            }
+            Action::MatchingBracket => {
+                self.jump_to_matching_bracket();
+            }
+            Action::HalfPageDown => {
+                self.scroll_half_page(true);
+            }
+            Action::HalfPageUp => {
+                self.scroll_half_page(false);
+            }
+            Action::PageDown => {
+                self.scroll_page(true);
+            }
+            Action::PageUp => {
+                self.scroll_page(false);
+            }
+            Action::GoToLine => {
+                self.push_jump();
+                self.move_to_line(count.map_or(usize::MAX, |n| n.saturating_sub(1)));
+                self.target_col = 0;
+                self.clamp_col_to_current_line();
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Like `gg`, a count goes to that line instead
+    fn go_to_first_line(&mut self, count: Option<usize>) {
+        self.push_jump();
+        self.move_to_line(count.map_or(0, |n| n.saturating_sub(1)));
+        self.target_col = 0;
+        self.clamp_col_to_current_line();
+    }
+
+    /// Like `yy`
+    fn yank_line(&mut self) {
+        if let Some(line) = self.get_current_line() {
+            self.set_register(RegisterContent::Lines(vec![line.clone()]));
+        }
+    }
+
+    /// Handles the second key of a multi-key command started with `pending`
+    /// Returns true if the program should continue
+    fn handle_pending_keypress(&mut self, pending: char, key: &Key) -> bool {
+        let count = self.pending_count.take();
+        let motion_count = self.motion_count.take();
+        if matches!(pending, 'd' | 'c')
+            && let Key::Char(c @ '0'..='9') = key
+            && (*c != '0' || motion_count.is_some())
+        {
+            let digit = c.to_digit(10).unwrap_or_default() as usize;
+            let motion_count = motion_count.unwrap_or(0);
+            self.motion_count = Some(motion_count.saturating_mul(10).saturating_add(digit));
+            self.pending = Some(pending);
+            self.pending_count = count;
+            return true;
+        }
+        // Both counts multiply, `2d3w` deletes 6 words
+        let count = match (count, motion_count) {
+            (Some(count), Some(motion_count)) => Some(count.saturating_mul(motion_count)),
+            (count, motion_count) => count.or(motion_count),
+        };
+        match (pending, key) {
+            ('g', Key::Char('g')) => {
+                self.go_to_first_line(count);
+            }
+            ('g', Key::Char('j')) => {
+                self.handle_motion(Action::DisplayDown, count);
+            }
+            ('g', Key::Char('k')) => {
+                self.handle_motion(Action::DisplayUp, count);
+            }
+            ('d', Key::Char('d')) => {
+                self.delete_lines(count.unwrap_or(1));
+            }
+            ('d', _) => {
+                if let Some(&action) = self.keymap.normal.get(key) {
+                    self.delete_motion(action, count);
+                }
+            }
+            ('c', _) => {
+                if let Some(&action) = self.keymap.normal.get(key) {
+                    self.change_motion(action, count);
+                }
+            }
+            ('y', Key::Char('y')) => {
+                self.yank_line();
+            }
+            ('r', Key::Char(c)) => {
+                self.replace_char_under_cursor(*c);
+            }
+            ('"', Key::Char(c @ ('a'..='z' | 'A'..='Z' | '"'))) => {
+                self.selected_register = Some(*c);
+                // Kept for the command, e.g. `3"add`
+                self.pending_count = count;
+            }
+            ('q', Key::Char(c @ ('a'..='z' | 'A'..='Z'))) => {
+                self.start_macro_recording(*c);
+            }
+            ('@', Key::Char(c @ ('a'..='z' | 'A'..='Z' | '@'))) => {
+                self.play_macro(*c, count.unwrap_or(1));
+            }
+            ('m', Key::Char(c @ 'a'..='z')) => {
+                self.set_mark(*c);
+            }
+            ('`' | '\'', Key::Char(c @ 'a'..='z')) => {
+                self.jump_to_mark(*c, pending == '\'');
+            }
+            ('r', Key::Tab) => {
+                self.replace_char_under_cursor('\t');
+            }
+            // Like :x, only writes when there are changes
+            ('Z', Key::Char('Z')) => {
+                return self.dirty && self.handle_command(Command::SaveAndQuit { filename: None });
+            }
+            ('z', Key::Char(c @ ('t' | 'z' | 'b'))) => {
+                // `zb` puts the last screen row of a wrapped cursor line at the bottom
+                let cursor_rows = if self.config.wrap {
+                    self.wrapped_rows(self.current_line_index(), self.text_cols())
+                } else {
+                    1
+                };
+                let last_row = self.text_rows().saturating_sub(cursor_rows);
+                let rows_above = match c {
+                    't' => 0,
+                    'z' => last_row / 2,
+                    _ => last_row,
+                };
+                self.scroll_cursor_line_to(rows_above);
+            }
+            ('Z', Key::Char('Q')) => {
+                return self.handle_command(Command::Quit { forcefully: true });
+            }
+            _ => {
+                debug!("Cancelled pending {pending:?} with {key:?}");
+            }
+        }
+
+        true
+    }
+
+    /// Returns true if the program should continue
+    fn handle_keypress_normal(&mut self, key: &Key) -> bool {
+        if let Some(pending) = self.pending.take() {
+            return self.handle_pending_keypress(pending, key);
+        }
+
+        if self.accumulate_count(key) {
+            return true;
+        }
+        let count = self.pending_count.take();
+        let Some(&action) = self.keymap.normal.get(key) else {
+            debug!("{key:?}");
+            return true;
+        };
+        if self.handle_motion(action, count) {
+            return true;
+        }
 
+        match action {
+            Action::Prefix(c) => {
+                self.pending = Some(c);
+                // Kept for the command, e.g. `2dw` or `3"add`
+                self.pending_count = count;
+            }
+            Action::RecordMacro => {
+                if self.recording_macro().is_some() {
+                    self.stop_macro_recording();
+                } else {
+                    self.pending = Some('q');
+                }
+            }
+            Action::ChangeToLineEnd => {
+                self.change_motion(Action::LineEnd, count);
+            }
+            Action::JoinLines => {
+                self.join_with_next_line();
+            }
+            Action::ToggleCase => {
+                self.toggle_case(count.unwrap_or(1));
+            }
+            Action::Increment => {
+                self.add_to_number(count.unwrap_or(1) as i128);
+            }
+            Action::Decrement => {
+                self.add_to_number(-(count.unwrap_or(1) as i128));
+            }
+            Action::RepeatChange => {
+                self.repeat_last_change(count);
+            }
+            Action::PasteAfter => {
+                self.paste(true);
+            }
+            Action::PasteBefore => {
+                self.paste(false);
+            }
+            Action::VisualMode => {
+                self.enable_visual_mode(false);
+            }
+            Action::VisualLineMode => {
+                self.enable_visual_mode(true);
+            }
+            Action::DeleteChar => {
+                if self.get_current_line().is_some_and(|line| !line.is_empty()) {
+                    self.save_undo_point();
+
+                    let index = self.current_line_index();
+                    let line = &mut self.text_lines[index];
+                    let col = self.cursor_pos.col.min(line.len() - 1);
+                    // Combining characters go away with their base
+                    let removed: String = (col..line.next_grapheme(col))
+                        .filter_map(|_| line.remove_at(col))
+                        .collect();
+                    // Vim keeps the cursor on the last character
+                    self.cursor_pos.col =
+                        line.grapheme_start(col.min(line.len().saturating_sub(1)));
+                    self.target_col = self.cursor_pos.col;
                     self.dirty = true;
-                    // This is not how Vim does it but whatever for now
-                    self.clamp_col_to_current_line();
+                    self.set_register(RegisterContent::Chars(removed));
                 }
             }
-            Key::Char(b'i') => {
+            Action::Insert => {
+                self.save_undo_point();
+                self.count_insertion(count, false);
                 self.enable_insertion_mode();
             }
-            Key::Char(b'I') => {
-                self.cursor_pos.col = 0;
-                self.enable_insertion_mode();
+            Action::InsertAtLineStart => {
+                if let Some(line) = self.get_current_line() {
+                    self.cursor_pos.col = line.first_non_blank();
+                    self.target_col = self.cursor_pos.col;
+                    self.save_undo_point();
+                    self.count_insertion(count, false);
+                    self.enable_insertion_mode();
+                }
             }
-            Key::Char(b'A') => {
+            Action::Append => {
                 if let Some(line) = self.get_current_line() {
-                    self.cursor_pos.col = line.len();
+                    self.cursor_pos.col = (self.cursor_pos.col + 1).min(line.len());
+                    self.save_undo_point();
+                    self.count_insertion(count, false);
                     self.enable_insertion_mode();
                 }
             }
-            Key::Char(b'o') => {
-                if self.cursor_pos.row >= self.window_size.row - 3 {
-                    self.text_offset += 1;
-                } else {
-                    self.cursor_pos.row += 1;
+            Action::AppendAtLineEnd => {
+                if let Some(line) = self.get_current_line() {
+                    self.cursor_pos.col = line.len();
+                    self.save_undo_point();
+                    self.count_insertion(count, false);
+                    self.enable_insertion_mode();
                 }
-                self.add_new_line();
-                self.enable_insertion_mode();
             }
-            Key::Char(b'O') => {
-                self.add_new_line();
-                self.enable_insertion_mode();
+            Action::OpenLineBelow => {
+                self.save_undo_point();
+                self.count_insertion(count, true);
+                self.open_line(self.current_line_index() + 1);
+            }
+            Action::OpenLineAbove => {
+                self.save_undo_point();
+                self.count_insertion(count, true);
+                self.open_line(self.current_line_index());
             }
-            Key::Char(b':') => {
+            Action::Undo => {
+                self.undo();
+            }
+            Action::Redo => {
+                self.redo();
+            }
+            Action::OlderJump => {
+                self.walk_jumps(true);
+            }
+            Action::NewerJump => {
+                self.walk_jumps(false);
+            }
+            Action::FileInfo => {
+                self.message = Message {
+                    msg: self.file_info(),
+                    r#type: MessageType::Info,
+                };
+            }
+            Action::CommandLine => {
                 self.current_mode = Mode::Command;
             }
-            // TODO: change this to ZZ
-            Key::Char(b'Z') => {
-                return false;
+            Action::Search => {
+                self.current_mode = Mode::Search {
+                    origin: (self.current_line_index(), self.cursor_pos.col),
+                    view: (self.text_offset, self.col_offset),
+                };
+            }
+            Action::SearchNext => {
+                self.repeat_search(Direction::Forward);
+            }
+            Action::SearchPrevious => {
+                self.repeat_search(Direction::Backward);
+            }
+            Action::FirstLine => {
+                self.go_to_first_line(count);
+            }
+            Action::DeleteLine => {
+                self.delete_lines(count.unwrap_or(1));
+            }
+            Action::YankLine => {
+                self.yank_line();
+            }
+            Action::WriteAndQuit => {
+                return self.dirty && self.handle_command(Command::SaveAndQuit { filename: None });
+            }
+            Action::QuitWithoutWriting => {
+                return self.handle_command(Command::Quit { forcefully: true });
             }
-
             _ => {
-                debug!("{key:?}");
+                debug!("{action:?} in Normal mode");
             }
         }
 
         true
     }
 
+    /// Writes the insertion buffer back into the current line and goes back to Normal mode
+    fn commit_insertion(&mut self, buffer: &GapBuffer) {
+        self.current_mode = Mode::Normal;
+        self.target_col = self.cursor_pos.col;
+
+        if let Some(line) = self.get_current_line_mut() {
+            *line = buffer.to_line();
+        }
+    }
+
     /// Returns true if the program should continue
-    fn handle_keypress_insertion(&mut self, key: &Key, mut buffer: SplitBuffer) -> bool {
+    fn handle_keypress_insertion(&mut self, key: &Key, mut buffer: GapBuffer) -> bool {
         match key {
             Key::Char(c) => {
                 // TODO: check end of window
-                buffer.start.push(*c as char);
-                self.cursor_pos.col += 1;
+                buffer.insert(*c);
                 self.dirty = true;
             }
-            Key::Escape => {
-                self.current_mode = Mode::Normal;
-                self.target_col = self.cursor_pos.col;
-
-                if let Some(line) = self.get_current_line_mut() {
-                    line.reserve(buffer.start.len() + buffer.end.len());
-                    line.clear();
-                    line.extend(buffer.start.drain(..));
-                    line.extend(buffer.end.drain(..));
+            Key::Escape | Key::Ctrl('c') => {
+                // With a count the text is typed again, without being recorded twice
+                for key in self.take_insertion_repeat() {
+                    self.handle_keypress_insertion(&key, buffer);
+                    let Mode::Insertion { buffer: typed } =
+                        std::mem::replace(&mut self.current_mode, Mode::Normal)
+                    else {
+                        unreachable!("only Escape leaves insertion mode");
+                    };
+                    buffer = typed;
                 }
+                self.commit_insertion(&buffer);
+                // The whole insertion session is a single undo step
+                self.history.discard_if_unchanged(&self.text_lines);
 
                 return true;
             }
-            Key::Delete => {
-                if buffer.end.pop_front().is_some() {
-                    self.dirty = true;
+            Key::ArrowUp | Key::ArrowDown => {
+                // Moving ends the text to repeat, like in Vim
+                self.count_insertion(None, false);
+                self.commit_insertion(&buffer);
+                if matches!(key, Key::ArrowUp) {
+                    self.move_up();
+                } else {
+                    self.move_down();
                 }
+                // Unlike in Normal mode the cursor can be after the last character
+                let len = self.get_current_line().map_or(0, |l| l.len());
+                self.cursor_pos.col = self.target_col.min(len);
+                self.enable_insertion_mode();
+
+                return true;
+            }
+            Key::ArrowLeft => {
+                buffer.move_to(buffer.cursor().saturating_sub(1));
+            }
+            Key::ArrowRight => {
+                buffer.move_to(buffer.cursor() + 1);
+            }
+            Key::Home => {
+                buffer.move_to(0);
+            }
+            Key::End => {
+                buffer.move_to(buffer.len());
+            }
+            Key::Delete if buffer.delete_after().is_some() => {
+                self.dirty = true;
+            }
+            Key::Ctrl('w') if buffer.delete_word_before() > 0 => {
+                self.dirty = true;
+            }
+            Key::Ctrl('u') if buffer.delete_to_line_start() > 0 => {
+                self.dirty = true;
             }
             Key::Backspace => {
-                if self.cursor_pos.col != 0 && buffer.start.pop().is_some() {
-                    self.cursor_pos.col -= 1;
+                if buffer.delete_before().is_some() {
                     self.dirty = true;
-                } else if self.cursor_pos.row + self.text_offset != 0
-                    && let Some(line) = self.get_current_line_mut()
-                {
-                    line.clear();
-                    let lines_below =
-                        &mut self.text_lines[self.cursor_pos.row + self.text_offset..];
-                    lines_below.rotate_left(1);
-
+                } else if self.current_line_index() != 0 {
+                    // Join with the previous line, the content of the current one is
+                    // already after the cursor
+                    let current = self.current_line_index();
+                    self.text_lines.remove(current);
+                    self.move_to_line(current - 1);
+                    self.join_marks(current, current - 1, self.text_lines[current - 1].len());
+
+                    buffer.extend(self.text_lines[current - 1].chars());
                     self.dirty = true;
-
-                    if self.cursor_pos.row == 0 {
-                        self.text_offset -= 1;
-                    } else {
-                        self.cursor_pos.row -= 1;
-                    }
-
-                    buffer
-                        .start
-                        .extend(self.text_lines[self.cursor_pos.row + self.text_offset].chars());
-                    self.cursor_pos.col = buffer.start.len();
                 }
             }
             Key::Enter => {
                 if let Some(line) = self.get_current_line_mut() {
-                    line.reserve(buffer.start.len());
-                    line.clear();
-                    line.extend(buffer.start.drain(..));
+                    buffer.split_before_into(line);
                 }
-                if self.cursor_pos.row >= self.window_size.row - 3 {
-                    self.text_offset += 1;
-                } else {
-                    self.cursor_pos.row += 1;
+                // The rest of the line stays in the buffer and goes on the new line,
+                // with the indentation of the current line instead of its own
+                if self.config.auto_indent
+                    && let Some(line) = self.get_current_line()
+                {
+                    while matches!(buffer.after().first(), Some(' ' | '\t')) {
+                        buffer.delete_after();
+                    }
+                    buffer.extend(line.leading_whitespace().chars());
                 }
-                self.add_new_line();
-                buffer.start.clear();
+                self.add_new_line(self.current_line_index() + 1);
             }
             Key::Tab => {
                 // TODO: check end of window
-                buffer.start.extend_from_slice(&[' ', ' ', ' ', ' ']);
-                self.cursor_pos.col += 4;
+                if self.config.expand_tab {
+                    // Fill up to the next tab stop
+                    let width = buffer.cursor_width();
+                    let spaces = self.config.tab_width - width % self.config.tab_width;
+                    buffer.extend(std::iter::repeat_n(' ', spaces));
+                } else {
+                    buffer.insert('\t');
+                }
                 self.dirty = true;
             }
             _ => {}
         }
 
+        self.cursor_pos.col = buffer.cursor();
         self.current_mode = Mode::Insertion { buffer };
         true
     }
@@ -496,7 +1890,7 @@ impl State {
         match key {
             Key::Char(c) => {
                 // TODO: check end of window
-                self.command_buf.push(*c as char);
+                self.command_buf.push(*c);
             }
             Key::Escape => {
                 self.current_mode = Mode::Normal;
@@ -505,12 +1899,18 @@ impl State {
 
                 return true;
             }
-            Key::ArrowUp => todo!(),
-            Key::ArrowDown => todo!(),
-            Key::ArrowLeft => todo!(),
-            Key::ArrowRight => todo!(),
-            Key::Delete => todo!(),
-            Key::Tab => todo!(),
+            // TODO: command line editing and history
+            Key::ArrowUp
+            | Key::ArrowDown
+            | Key::ArrowLeft
+            | Key::ArrowRight
+            | Key::Home
+            | Key::End
+            | Key::PageUp
+            | Key::PageDown
+            | Key::Delete
+            | Key::Tab
+            | Key::Ctrl(_) => {}
             Key::Backspace => {
                 if self.command_buf.pop().is_none() {
                     self.current_mode = Mode::Normal;
@@ -546,104 +1946,1051 @@ fn main() -> color_eyre::Result<()> {
     setup_logger()?;
 
     let mut lines: Vec<Line> = Vec::new();
+    let mut end_of_line = true;
     let mut filename = None;
-    let mut file_info = String::with_capacity(30);
+    let mut file_info = String::new();
     if let Some(arg) = std::env::args_os().nth(1) {
         let path: PathBuf = arg.into();
-        // TODO: make this a future or some shit
-        if let Ok(f) = File::open(&path) {
-            let reader = BufReader::new(&f);
-            lines = reader
-                .lines()
-                .map(|l| Line::with_string(l.unwrap_or_default()))
-                .collect();
-
-            let metadata = f.metadata()?;
-            format!(
-                "\"{}\" {}L, {}B",
-                path.file_name()
-                    .wrap_err("Failed to read filename")?
-                    .display(),
-                lines.len(),
-                metadata.len()
-            )
-            .clone_into(&mut file_info);
-        }
-        filename = Some(path);
-    }
-    if lines.is_empty() {
-        lines.push(Line::new());
-    }
-
-    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
-
-    cvt(unsafe { libc::tcgetattr(STDIN_FILENO, &raw mut termios) })
-        .wrap_err("Could not get terminal parameters")?;
-
-    let mut state = State {
-        previous_io_settings: termios,
-        current_io_settings: termios,
-        window_size: get_window_size().ok_or_eyre("Could not get window size")?,
-        cursor_pos: WindowSize { col: 0, row: 0 },
-        target_col: 0,
-        text_lines: lines,
-        text_offset: 0,
-        current_mode: Mode::Normal,
-        command_buf: String::new(),
-        message: Message {
-            msg: file_info,
-            r#type: MessageType::Info,
-        },
-        save_file: filename,
-        dirty: false,
-    };
-
-    unsafe {
-        libc::cfmakeraw(&raw mut state.current_io_settings);
+        match load_file(&path) {
+            Ok(content) => {
+                lines = content.lines;
+                end_of_line = content.end_of_line;
+                file_info = content.info;
+                filename = Some(path);
+            }
+            // Without a file name `:w` cannot overwrite the file with what was not read
+            Err(e) => {
+                warn!("Could not open {}: {e}", path.display());
+                file_info = format!("\"{}\" [{}]", path.display(), e.kind());
+            }
+        }
     }
-    state.current_io_settings.c_cc[libc::VMIN] = 0;
-    state.current_io_settings.c_cc[libc::VTIME] = 1;
-
-    cvt(unsafe {
-        libc::tcsetattr(
-            STDIN_FILENO,
-            TCSAFLUSH,
-            &raw const state.current_io_settings,
-        )
-    })
-    .wrap_err("Could not set terminal parameters")?;
+    let (window_size, source) = get_window_size();
+    if source != WindowSizeSource::Terminal {
+        warn!("Could not get window size from the terminal, using {window_size:?} from {source:?}");
+    }
+    let mut state = State::new(lines, filename, window_size);
+    state.end_of_line = end_of_line;
+    if let Ok(name) = std::env::var("VIM_RS_THEME") {
+        match Theme::by_name(&name) {
+            Some(theme) => state.config.theme = theme,
+            None => warn!("Unknown theme {name:?}, using the default one"),
+        }
+    }
+    state.message.msg = file_info;
 
-    state.init_ui().wrap_err("Failed to initialize UI")?;
+    let _terminal = RawTerminal::enable()?;
+    install_sigwinch_handler()?;
+    state.draw_ui().wrap_err("Failed to initialize UI")?;
 
-    let mut stdin_lock = std::io::stdin().lock();
+    let mut input = KeyReader::new(TerminalInput);
     loop {
-        match &read_key(&mut stdin_lock) {
+        match &input.read_key() {
             Ok(key) => {
-                let current_mode = std::mem::replace(&mut state.current_mode, Mode::Normal);
-
-                // Maybe there is a way to put the handle method in the enum?
-                let should_exit = !match current_mode {
-                    Mode::Normal => state.handle_keypress_normal(key),
-                    Mode::Insertion { buffer } => state.handle_keypress_insertion(key, buffer),
-                    Mode::Command => state.handle_keypress_command(key),
-                };
-
-                if should_exit {
+                if !state.handle_keypress(key) {
                     break;
                 }
             }
-            Err(e) => {
-                if matches!(e, SequenceParsingError::NoChar) {
+            Err(SequenceParsingError::NoChar) => {
+                if !RESIZED.load(Ordering::Relaxed) {
                     continue;
                 }
+            }
+            Err(e) => {
                 warn!("Unsupported input: {e:?}");
                 "Received unsupported input".clone_into(&mut state.message.msg);
                 state.message.r#type = MessageType::Warning;
             }
         }
 
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            state.resize(get_window_size().0);
+        }
+
         state.draw_ui().wrap_err("Failed to draw UI")?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        DEFAULT_WINDOW_SIZE, State, WindowSize, WindowSizeSource,
+        highlight::KeywordHighlighter,
+        key::Key,
+        line::Line,
+        register::RegisterContent,
+        test_utils::{keys, lines, numbered_lines, state_with_lines, type_keys},
+        theme::{Background, CursorShape, Foreground},
+        window_size_from_env,
+    };
+
+    #[test]
+    fn delete_line() {
+        let mut state = state_with_lines(&["first", "second", "third"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('d'), Key::Char('d')],
+        );
+        assert_eq!(lines(&state), ["first", "third"]);
+        assert_eq!(state.current_line_index(), 1);
+        assert!(
+            matches!(&state.register, Some(RegisterContent::Lines(lines)) if *lines == [Line::from("second")])
+        );
+
+        type_keys(&mut state, &[Key::Char('d'), Key::Char('d')]);
+        assert_eq!(lines(&state), ["first"]);
+        assert_eq!(state.current_line_index(), 0);
+
+        let mut state = state_with_lines(&["1", "2", "3", "4", "5"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('2'),
+                Key::Char('d'),
+                Key::Char('d'),
+            ],
+        );
+        assert_eq!(lines(&state), ["1", "4", "5"]);
+        assert_eq!(state.current_line_index(), 1);
+        assert!(
+            matches!(&state.register, Some(RegisterContent::Lines(lines)) if *lines == [Line::from("2"), Line::from("3")])
+        );
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('9'),
+                Key::Char('d'),
+                Key::Char('d'),
+                Key::Char('u'),
+            ],
+        );
+        assert_eq!(lines(&state), ["1", "4", "5"]);
+    }
+
+    #[test]
+    fn delete_every_line() {
+        let mut state = state_with_lines(&["first", "second", "third"]);
+        type_keys(&mut state, &[Key::Char('G')]);
+        for _ in 0..4 {
+            type_keys(&mut state, &[Key::Char('d'), Key::Char('d')]);
+        }
+        assert_eq!(lines(&state), [""]);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('k'),
+                Key::Char('G'),
+                Key::Char('x'),
+            ],
+        );
+        type_keys(
+            &mut state,
+            &[Key::Char('p'), Key::Char('d'), Key::Char('d')],
+        );
+        assert_eq!(lines(&state), [""]);
+        assert_eq!(state.current_line_index(), 0);
+
+        let mut state = state_with_lines(&[]);
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('d'), Key::Char('d')],
+        );
+        assert_eq!(lines(&state), [""]);
+    }
+
+    #[test]
+    fn yank_and_paste() {
+        let mut state = state_with_lines(&["first", "second"]);
+        // Empty register
+        type_keys(&mut state, &[Key::Char('p')]);
+        assert_eq!(lines(&state), ["first", "second"]);
+
+        type_keys(
+            &mut state,
+            &[Key::Char('y'), Key::Char('y'), Key::Char('p')],
+        );
+        assert_eq!(lines(&state), ["first", "first", "second"]);
+        assert_eq!(state.current_line_index(), 1);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('d'),
+                Key::Char('d'),
+                Key::Char('P'),
+            ],
+        );
+        assert_eq!(lines(&state), ["first", "second", "first"]);
+        assert_eq!(state.current_line_index(), 1);
+    }
+
+    #[test]
+    fn auto_indent() {
+        let mut state = state_with_lines(&["\t  indented"]);
+        state.config.auto_indent = true;
+        type_keys(
+            &mut state,
+            &[Key::Char('A'), Key::Enter, Key::Char('a'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["\t  indented", "\t  a"]);
+
+        type_keys(&mut state, &[Key::Char('o'), Key::Char('b'), Key::Escape]);
+        type_keys(&mut state, &[Key::Char('O'), Key::Char('c'), Key::Escape]);
+        assert_eq!(lines(&state), ["\t  indented", "\t  a", "\t  c", "\t  b"]);
+
+        // Whitespace after the cursor is replaced by the indentation
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('0'),
+                Key::Char('l'),
+                Key::Char('i'),
+                Key::Enter,
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state)[2..4], ["\t", "\tc"]);
+
+        state.config.auto_indent = false;
+        type_keys(&mut state, &[Key::Char('o'), Key::Char('d'), Key::Escape]);
+        assert_eq!(lines(&state)[4], "d");
+    }
+
+    #[test]
+    fn insertion_delete_word() {
+        let mut state = state_with_lines(&["let x = foo", "bar"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('A'),
+                Key::Ctrl('w'),
+                Key::Ctrl('w'),
+                Key::Char('1'),
+            ],
+        );
+        assert_eq!(state.cursor_pos.col, 7);
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["let x 1", "bar"]);
+
+        // Stops at the start of the line
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('0'),
+                Key::Char('i'),
+                Key::Ctrl('w'),
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state), ["let x 1", "bar"]);
+    }
+
+    #[test]
+    fn insertion_delete_to_line_start() {
+        let mut state = state_with_lines(&["    foo bar"]);
+        type_keys(&mut state, &[Key::Char('A'), Key::Ctrl('u')]);
+        assert_eq!(state.cursor_pos.col, 4);
+        type_keys(&mut state, &[Key::Ctrl('u')]);
+        assert_eq!(state.cursor_pos.col, 0);
+        type_keys(&mut state, &[Key::Char('x'), Key::Escape]);
+        assert_eq!(lines(&state), ["x"]);
+    }
+
+    #[test]
+    fn ctrl_c_leaves_insertion() {
+        let mut state = state_with_lines(&["ab"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('A'), Key::Char('c'), Key::Ctrl('c')],
+        );
+        assert!(matches!(state.current_mode, crate::Mode::Normal));
+        assert_eq!(lines(&state), ["abc"]);
+        assert_eq!(state.target_col, 3);
+
+        // Undone in one step like with Escape
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["ab"]);
+    }
+
+    #[test]
+    fn backspace_joins_lines() {
+        let mut state = state_with_lines(&["ab", "cd", "ef"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('i'), Key::Backspace, Key::Escape],
+        );
+        assert_eq!(lines(&state), ["abcd", "ef"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 2));
+
+        // Nothing to join on the first line
+        type_keys(
+            &mut state,
+            &[Key::Char('0'), Key::Char('i'), Key::Backspace, Key::Escape],
+        );
+        assert_eq!(lines(&state), ["abcd", "ef"]);
+    }
+
+    #[test]
+    fn enter_splits_line() {
+        // Middle
+        let mut state = state_with_lines(&["hello world"]);
+        type_keys(&mut state, &[Key::Char('w'), Key::Char('i'), Key::Enter]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 0));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["hello ", "world"]);
+
+        // End
+        let mut state = state_with_lines(&["hello", "after"]);
+        type_keys(&mut state, &[Key::Char('A'), Key::Enter, Key::Escape]);
+        assert_eq!(lines(&state), ["hello", "", "after"]);
+        assert_eq!(state.current_line_index(), 1);
+
+        // Start
+        let mut state = state_with_lines(&["hello"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('i'), Key::Enter, Key::Char('x'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["", "xhello"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 1));
+    }
+
+    #[test]
+    fn delete_key() {
+        let mut state = state_with_lines(&["abcd"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('l'),
+                Key::Char('i'),
+                Key::Delete,
+                Key::Delete,
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state), ["ad"]);
+
+        type_keys(&mut state, &[Key::Char('$'), Key::Delete]);
+        assert_eq!(lines(&state), ["a"]);
+        type_keys(&mut state, &[Key::Delete, Key::Delete]);
+        assert_eq!(lines(&state), [""]);
+    }
+
+    #[test]
+    fn replace_char() {
+        let mut state = state_with_lines(&["abc", ""]);
+        type_keys(
+            &mut state,
+            &[Key::Char('l'), Key::Char('r'), Key::Char('日')],
+        );
+        assert_eq!(lines(&state), ["a日c", ""]);
+        assert_eq!(state.cursor_pos.col, 1);
+        assert!(state.dirty);
+
+        type_keys(&mut state, &[Key::Char('r'), Key::Escape, Key::Char('x')]);
+        assert_eq!(lines(&state), ["ac", ""]);
+
+        type_keys(&mut state, &[Key::Char('r'), Key::Char('2')]);
+        assert_eq!(lines(&state), ["a2", ""]);
+
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('r'), Key::Char('z')],
+        );
+        assert_eq!(lines(&state), ["a2", ""]);
+
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["ac", ""]);
+    }
+
+    #[test]
+    fn join_lines() {
+        let mut state = state_with_lines(&["first", "    second", "", "third"]);
+        type_keys(&mut state, &[Key::Char('J')]);
+        assert_eq!(lines(&state), ["first second", "", "third"]);
+        assert_eq!(state.cursor_pos.col, 5);
+
+        type_keys(&mut state, &[Key::Char('J')]);
+        assert_eq!(lines(&state), ["first second", "third"]);
+        assert_eq!(state.cursor_pos.col, 11);
+
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('J')]);
+        assert_eq!(lines(&state), ["first second", "third"]);
+
+        type_keys(&mut state, &[Key::Char('u'), Key::Char('u')]);
+        assert_eq!(lines(&state), ["first", "    second", "", "third"]);
+    }
+
+    #[test]
+    fn word_end() {
+        let mut state = state_with_lines(&["foo bar.  ", "", "  ...baz"]);
+        let mut positions = Vec::new();
+        for _ in 0..5 {
+            type_keys(&mut state, &[Key::Char('e')]);
+            positions.push((state.current_line_index(), state.cursor_pos.col));
+        }
+        assert_eq!(positions, [(0, 2), (0, 6), (0, 7), (2, 4), (2, 7)]);
+
+        type_keys(&mut state, &[Key::Char('e')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 7));
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('g'),
+                Key::Char('g'),
+                Key::Char('3'),
+                Key::Char('e'),
+            ],
+        );
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 7));
+    }
+
+    #[test]
+    fn word_backward() {
+        let mut state = state_with_lines(&["foo bar", "", "  baz.qux"]);
+        type_keys(&mut state, &[Key::Char('G'), Key::Char('$')]);
+        let mut positions = Vec::new();
+        for _ in 0..6 {
+            type_keys(&mut state, &[Key::Char('b')]);
+            positions.push((state.current_line_index(), state.cursor_pos.col));
+        }
+        assert_eq!(positions, [(2, 6), (2, 5), (2, 2), (1, 0), (0, 4), (0, 0)]);
+
+        type_keys(&mut state, &[Key::Char('b')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+
+        type_keys(
+            &mut state,
+            &[Key::Char('G'), Key::Char('2'), Key::Char('b')],
+        );
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 4));
+    }
+
+    #[test]
+    fn move_by_grapheme() {
+        let mut state = state_with_lines(&["ae\u{301}🇫🇷b"]);
+        let mut cols = Vec::new();
+        for _ in 0..3 {
+            type_keys(&mut state, &[Key::Char('l')]);
+            cols.push(state.cursor_pos.col);
+        }
+        for _ in 0..3 {
+            type_keys(&mut state, &[Key::Char('h')]);
+            cols.push(state.cursor_pos.col);
+        }
+        assert_eq!(cols, [1, 3, 5, 3, 1, 0]);
+
+        type_keys(&mut state, &[Key::Char('l'), Key::Char('x')]);
+        assert_eq!(lines(&state), ["a🇫🇷b"]);
+    }
+
+    #[test]
+    fn word_motions_by_grapheme() {
+        let mut state = state_with_lines(&["cafe\u{301} ab\u{301}"]);
+        type_keys(&mut state, &keys("$"));
+        assert_eq!(state.cursor_pos.col, 7);
+        type_keys(&mut state, &keys("0e"));
+        assert_eq!(state.cursor_pos.col, 3);
+        type_keys(&mut state, &keys("e"));
+        assert_eq!(state.cursor_pos.col, 7);
+        type_keys(&mut state, &keys("0w"));
+        assert_eq!(state.cursor_pos.col, 6);
+
+        type_keys(&mut state, &keys("$rx"));
+        assert_eq!(lines(&state), ["cafe\u{301} ax"]);
+        type_keys(&mut state, &keys("0de"));
+        assert_eq!(lines(&state), [" ax"]);
+    }
+
+    #[test]
+    fn half_page_scrolling() {
+        let mut state = numbered_lines(50);
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('$')]);
+
+        type_keys(&mut state, &[Key::Ctrl('d')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (11, 12));
+        assert_eq!(state.cursor_pos.col, 6);
+
+        type_keys(&mut state, &[Key::Ctrl('d'), Key::Ctrl('d')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (28, 34));
+        type_keys(&mut state, &[Key::Ctrl('d'), Key::Ctrl('d')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (28, 49));
+
+        type_keys(&mut state, &[Key::Ctrl('u')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (17, 38));
+        for _ in 0..4 {
+            type_keys(&mut state, &[Key::Ctrl('u')]);
+        }
+        assert_eq!((state.text_offset, state.current_line_index()), (0, 0));
+    }
+
+    #[test]
+    fn page_scrolling() {
+        let mut state = numbered_lines(50);
+
+        type_keys(&mut state, &[Key::Ctrl('f')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (20, 20));
+        type_keys(&mut state, &[Key::Ctrl('f')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (28, 49));
+        type_keys(&mut state, &[Key::Ctrl('f')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (28, 49));
+
+        type_keys(&mut state, &[Key::Ctrl('b')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (8, 29));
+        type_keys(&mut state, &[Key::Ctrl('b')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (0, 21));
+        type_keys(&mut state, &[Key::Ctrl('b')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (0, 0));
+
+        type_keys(&mut state, &[Key::PageDown, Key::PageDown, Key::PageUp]);
+        assert_eq!((state.text_offset, state.current_line_index()), (8, 29));
+
+        let mut state = state_with_lines(&["short", "file"]);
+        type_keys(&mut state, &[Key::Ctrl('f')]);
+        assert_eq!((state.text_offset, state.current_line_index()), (0, 1));
+    }
+
+    #[test]
+    fn reposition_viewport() {
+        let mut state = numbered_lines(50);
+        let z = |c| [Key::Char('z'), Key::Char(c)];
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('2'),
+                Key::Char('0'),
+                Key::Char('G'),
+                Key::Char('$'),
+            ],
+        );
+        type_keys(&mut state, &z('t'));
+        assert_eq!((state.text_offset, state.current_line_index()), (19, 19));
+        assert_eq!((state.cursor_pos.row, state.cursor_pos.col), (0, 6));
+        type_keys(&mut state, &z('z'));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (9, 10));
+        type_keys(&mut state, &z('b'));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (0, 19));
+        assert_eq!(state.current_line_index(), 19);
+
+        // Not past the start or end of the buffer
+        type_keys(&mut state, &[Key::Char('g'), Key::Char('g')]);
+        type_keys(&mut state, &z('z'));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (0, 0));
+        type_keys(&mut state, &[Key::Char('G')]);
+        type_keys(&mut state, &z('t'));
+        assert_eq!((state.text_offset, state.current_line_index()), (28, 49));
+    }
+
+    #[test]
+    fn go_to_line() {
+        let mut state = numbered_lines(50);
+        type_keys(&mut state, &keys("5gg"));
+        assert_eq!(state.current_line_index(), 4);
+        type_keys(&mut state, &keys("G"));
+        assert_eq!(state.current_line_index(), 49);
+        type_keys(&mut state, &keys("gg"));
+        assert_eq!(state.current_line_index(), 0);
+        type_keys(&mut state, &keys("30G"));
+        assert_eq!(state.current_line_index(), 29);
+        type_keys(&mut state, &keys("99gg"));
+        assert_eq!(state.current_line_index(), 49);
+    }
+
+    #[test]
+    fn scroll_cursor_line_wrapped() {
+        let lines = vec![Line::with_string("x".repeat(15)); 10];
+        let mut state = State::new(lines, None, WindowSize { col: 14, row: 10 });
+        state.config.wrap = true;
+
+        // Every line takes two of the eight text rows
+        type_keys(&mut state, &keys("6Gzt"));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (5, 0));
+        type_keys(&mut state, &keys("zz"));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (4, 1));
+        type_keys(&mut state, &keys("zb"));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (2, 3));
+        type_keys(&mut state, &keys("Gzt"));
+        assert_eq!((state.text_offset, state.cursor_pos.row), (6, 3));
+    }
+
+    #[test]
+    fn sticky_col_after_edits() {
+        let mut state = state_with_lines(&["a long line here", "short", "another long line"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('$'), Key::Char('x'), Key::Char('j')],
+        );
+        assert_eq!(state.cursor_pos.col, 4);
+        type_keys(&mut state, &[Key::Char('k')]);
+        assert_eq!(state.cursor_pos.col, 14);
+
+        // The column left by the edit is the one remembered, not the one before it
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('0'),
+                Key::Char('w'),
+                Key::Char('d'),
+                Key::Char('w'),
+            ],
+        );
+        assert_eq!(lines(&state)[0], "a line her");
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('j')]);
+        assert_eq!(state.cursor_pos.col, 2);
+
+        // Vertical motions alone keep the column
+        type_keys(
+            &mut state,
+            &[Key::Char('$'), Key::Char('k'), Key::Char('j')],
+        );
+        assert_eq!(state.cursor_pos.col, 16);
+        type_keys(
+            &mut state,
+            &[Key::Char('x'), Key::Char('k'), Key::Char('k')],
+        );
+        assert_eq!(state.cursor_pos.col, 9);
+        assert_eq!(state.target_col, 15);
+    }
+
+    #[test]
+    fn toggle_case() {
+        let mut state = state_with_lines(&["hello World", ""]);
+        type_keys(&mut state, &[Key::Char('~')]);
+        assert_eq!(lines(&state), ["Hello World", ""]);
+        assert_eq!(state.cursor_pos.col, 1);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('w'),
+                Key::Char('2'),
+                Key::Char('0'),
+                Key::Char('~'),
+            ],
+        );
+        assert_eq!(lines(&state), ["Hello wORLD", ""]);
+        assert_eq!(state.cursor_pos.col, 10);
+
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('~')]);
+        assert_eq!(lines(&state), ["Hello wORLD", ""]);
+
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["Hello World", ""]);
+    }
+
+    #[test]
+    fn home_and_end() {
+        let mut state = state_with_lines(&["日本語"]);
+        type_keys(&mut state, &[Key::End]);
+        assert_eq!(state.cursor_pos.col, 2);
+        type_keys(&mut state, &[Key::Home]);
+        assert_eq!(state.cursor_pos.col, 0);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('i'),
+                Key::End,
+                Key::Char('!'),
+                Key::Home,
+                Key::Char('>'),
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state), [">日本語!"]);
+    }
+
+    #[test]
+    fn arrows_in_insertion() {
+        let mut state = state_with_lines(&["first", "second"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('A'),
+                Key::Char('!'),
+                Key::ArrowDown,
+                Key::Char('?'),
+                Key::ArrowUp,
+                Key::ArrowUp,
+                Key::Char('.'),
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state), ["first!.", "second?"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 7));
+
+        let mut state = state_with_lines(&["日本"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('i'),
+                Key::ArrowLeft,
+                Key::ArrowRight,
+                Key::Char('a'),
+                Key::ArrowRight,
+                Key::ArrowRight,
+                Key::Char('b'),
+                Key::ArrowLeft,
+                Key::ArrowLeft,
+                Key::Char('c'),
+            ],
+        );
+        assert_eq!(state.cursor_pos.col, 3);
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["日ac本b"]);
+    }
+
+    #[test]
+    fn window_size_fallback() {
+        let (size, source) = window_size_from_env(Some("120"), Some(" 40 "));
+        assert_eq!(size, WindowSize { col: 120, row: 40 });
+        assert_eq!(source, WindowSizeSource::Environment);
+
+        let (size, source) = window_size_from_env(Some("100"), Some("lots"));
+        assert_eq!(size, WindowSize { col: 100, row: 24 });
+        assert_eq!(source, WindowSizeSource::Environment);
+
+        let (size, source) = window_size_from_env(None, Some("0"));
+        assert_eq!(size, DEFAULT_WINDOW_SIZE);
+        assert_eq!(source, WindowSizeSource::Default);
+    }
+
+    #[test]
+    fn horizontal_scrolling() {
+        let long = "a".repeat(100);
+        let mut state = state_with_lines(&[&long, "日本語"]);
+        let text_cols = state.text_cols();
+        assert_eq!(text_cols, 76);
+
+        type_keys(&mut state, &[Key::Char('$')]);
+        state.scroll_horizontally(text_cols);
+        assert_eq!(state.col_offset, 24);
+
+        type_keys(&mut state, &[Key::Char('h')]);
+        state.scroll_horizontally(text_cols);
+        assert_eq!(state.col_offset, 24);
+
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('$')]);
+        state.scroll_horizontally(text_cols);
+        assert_eq!(state.col_offset, 4);
+
+        type_keys(&mut state, &[Key::Char('0')]);
+        state.scroll_horizontally(text_cols);
+        assert_eq!(state.col_offset, 0);
+    }
+
+    #[test]
+    fn render() {
+        let long = format!("{}end", "a".repeat(80));
+        let mut state = state_with_lines(&["first", &long]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("first\x1b[K"));
+        assert!(frame.contains(&format!("  2 {}\x1b[K", "a".repeat(76))));
+        assert!(!frame.contains("end"));
+
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('$')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("aaend\x1b[K"));
+    }
+
+    #[test]
+    fn render_wrap() {
+        let long = format!("{}end", "a".repeat(80));
+        let mut state = state_with_lines(&["first", &long, "last"]);
+        state.config.wrap = true;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains(&format!("\x1b[2;1H  2 {}\x1b[K", "a".repeat(76))));
+        assert!(frame.contains("\x1b[3;1H    aaaaend\x1b[K"));
+        assert!(frame.contains("\x1b[4;1H  3 last\x1b[K"));
+
+        // The cursor goes to the row holding its column
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('$')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.ends_with("\x1b[3;11H\x1b[2 q\x1b[?25h"));
+        assert_eq!(state.col_offset, 0);
+    }
+
+    #[test]
+    fn display_row_motions() {
+        let lines = [
+            "a".repeat(25),
+            "bb".to_owned(),
+            format!("日{}", "c".repeat(12)),
+        ];
+        let mut state = State::new(
+            lines.map(Line::with_string).to_vec(),
+            None,
+            WindowSize { col: 14, row: 10 },
+        );
+        state.config.wrap = true;
+
+        type_keys(&mut state, &keys("3lgj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 13));
+        type_keys(&mut state, &keys("gj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 23));
+        // The next line is too short for the column
+        type_keys(&mut state, &keys("gj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 1));
+        // The column is kept through the short line
+        type_keys(&mut state, &keys("gj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 2));
+        type_keys(&mut state, &keys("k0gjgj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 9));
+        assert!(!state.move_display_row(true));
+        // The wide character covers the column
+        type_keys(&mut state, &keys("lgk"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 0));
+
+        // The wanted column is still the one under the second half of the wide character
+        type_keys(&mut state, &keys("2gk"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 21));
+
+        // Like `j` and `k` without wrapping
+        state.config.wrap = false;
+        type_keys(&mut state, &keys("gjgj"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 12));
+    }
+
+    #[test]
+    fn wrap_before_wide_char() {
+        let lines = ["first".to_owned(), format!("{}日x", "a".repeat(9))];
+        let mut state = State::new(
+            lines.map(Line::with_string).to_vec(),
+            None,
+            WindowSize { col: 14, row: 6 },
+        );
+        state.config.wrap = true;
+        assert_eq!(state.text_cols(), 10);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("\x1b[2;1H  2 aaaaaaaaa\x1b[K"));
+        assert!(frame.contains("\x1b[3;1H    日x\x1b[K"));
+
+        type_keys(&mut state, &keys("j9l"));
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.ends_with("\x1b[3;5H\x1b[2 q\x1b[?25h"));
+        type_keys(&mut state, &keys("l"));
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.ends_with("\x1b[3;7H\x1b[2 q\x1b[?25h"));
+    }
+
+    #[test]
+    fn wrap_scrolling() {
+        let lines = ["a".repeat(25), "b".to_owned(), "c".repeat(15)].map(Line::with_string);
+        let mut state = State::new(lines.to_vec(), None, WindowSize { col: 14, row: 6 });
+        state.config.wrap = true;
+        assert_eq!(state.text_cols(), 10);
+
+        // The first line takes 3 rows so the third one only fits after scrolling
+        type_keys(&mut state, &[Key::Char('j')]);
+        assert_eq!(state.text_offset, 0);
+        type_keys(&mut state, &[Key::Char('j')]);
+        assert_eq!(state.text_offset, 1);
+        assert_eq!(state.cursor_pos.row, 1);
+
+        type_keys(&mut state, &[Key::Char('k'), Key::Char('k')]);
+        assert_eq!(state.text_offset, 0);
+
+        // Typing at the end of the last visible line scrolls to keep the cursor in view
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('A')]);
+        type_keys(&mut state, &vec![Key::Char('x'); 10]);
+        state.render().unwrap();
+        assert_eq!(state.text_offset, 1);
+    }
+
+    #[test]
+    fn render_tabs() {
+        let mut state = state_with_lines(&["\tx", "a\tb", "abcd\tc", "日\tz", "ab\t\tend"]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(!frame.contains('\t'));
+        assert!(frame.contains("    x\x1b[K"));
+        assert!(frame.contains("  2 a   b\x1b[K"));
+        assert!(frame.contains("  3 abcd    c\x1b[K"));
+        assert!(frame.contains("  4 日  z\x1b[K"));
+        assert!(frame.contains("  5 ab      end\x1b[K"));
+
+        // The cursor column counts the expanded width
+        type_keys(&mut state, &[Key::Char('G'), Key::Char('$')]);
+        assert_eq!(state.cursor_display_col(), 10);
+
+        state.config.tab_width = 8;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("  2 a       b\x1b[K"));
+    }
+
+    #[test]
+    fn render_list() {
+        let mut state = state_with_lines(&["a b\t", "\tc  "]);
+        state.config.list = true;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("a·b»\x1b[48;2;54;58;79m$\x1b[K"));
+        assert!(frame.contains("  2 »   c··\x1b[49m$\x1b[K"));
+        // Rows after the end of the buffer have no line end
+        assert_eq!(frame.matches("$\x1b[K").count(), 2);
+
+        // The cursor stays on the same columns
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('l')]);
+        assert_eq!(state.cursor_display_col(), 4);
+
+        state.config.list = false;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("a b \x1b[K"));
+    }
+
+    #[test]
+    fn render_syntax() {
+        let mut state = state_with_lines(&["let x = \"日\";", "x1 2"]);
+        state.highlighter = Some(Box::new(KeywordHighlighter::new(&["let"])));
+        let theme = state.config.theme.clone();
+        let keyword = Foreground(theme.keyword_foreground);
+        let string = Foreground(theme.string_foreground);
+        let number = Foreground(theme.number_foreground);
+        let background = Background(theme.cursor_line_background);
+
+        // The cursor line keeps its background
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains(&format!(
+            "  1 {background}{keyword}let\x1b[39m x = {string}\"日\"\x1b[39m;\x1b[K"
+        )));
+        assert!(frame.contains(&format!("  2 x1 {number}2\x1b[39m\x1b[K")));
+
+        // Colors take no room
+        type_keys(&mut state, &[Key::Char('$')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("\x1b[1;17H"));
+
+        // Text being typed is colored too
+        type_keys(&mut state, &[Key::Char('A'), Key::Char('3')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains(&format!(";{number}3\x1b[39m\x1b[K")));
+
+        type_keys(&mut state, &[Key::Escape]);
+        state.config.syntax = false;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("let x = \"日\";3\x1b[K"));
+    }
+
+    #[test]
+    fn redraw_changed_rows() {
+        let mut state = state_with_lines(&["first", "second", "third"]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.starts_with("\x1b[?25l\x1b[2J"));
+        assert!(frame.contains("third"));
+        assert!(frame.ends_with("\x1b[2 q\x1b[?25h"));
+
+        type_keys(&mut state, &[Key::Char('i')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.ends_with("\x1b[6 q\x1b[?25h"));
+        state.config.insert_cursor = CursorShape::BlinkingUnderline;
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.ends_with("\x1b[3 q\x1b[?25h"));
+        type_keys(&mut state, &[Key::Escape]);
+
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(!frame.contains("\x1b[2J"));
+        assert!(!frame.contains("first"));
+
+        type_keys(&mut state, &[Key::Char('G'), Key::Char('x')]);
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("\x1b[1;1H"));
+        assert!(!frame.contains("\x1b[2;1H"));
+        assert!(frame.contains("\x1b[3;1H"));
+        assert!(frame.contains("hird"));
+
+        state.resize(WindowSize { col: 80, row: 24 });
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.starts_with("\x1b[?25l\x1b[2J"));
+    }
+
+    #[test]
+    fn gutter_width() {
+        let mut state = state_with_lines(&["a"; 5]);
+        assert_eq!(state.gutter_width(), 4);
+
+        let mut state_long = state_with_lines(&["a"; 1234]);
+        assert_eq!(state_long.gutter_width(), 5);
+        state_long.config.show_line_numbers = false;
+        assert_eq!(state_long.gutter_width(), 0);
+
+        state.config.show_line_numbers = false;
+        assert_eq!(state.gutter_width(), 0);
+    }
+
+    #[test]
+    fn relative_line_numbers() {
+        let mut state = state_with_lines(&["a"; 5]);
+        type_keys(&mut state, &[Key::Char('j'), Key::Char('j')]);
+        assert_eq!(state.line_number(0), 1);
+
+        state.config.relative_line_numbers = true;
+        assert_eq!(
+            (0..5).map(|n| state.line_number(n)).collect::<Vec<_>>(),
+            [2, 1, 3, 1, 2]
+        );
+
+        state.config.show_line_numbers = false;
+        assert_eq!(state.line_number(2), 0);
+        assert_eq!(state.gutter_width(), 4);
+    }
+
+    #[test]
+    fn status_line() {
+        let mut state = state_with_lines(&["日本語"]);
+        state.window_size.col = 30;
+        type_keys(&mut state, &[Key::Char('l'), Key::Char('x')]);
+        assert_eq!(state.status_line(), " NORMAL | [No Name] [+]   1:3 ");
+        assert_eq!(state.status_line().len(), 30);
+
+        type_keys(&mut state, &[Key::Char('A')]);
+        state.save_file = Some("file.txt".into());
+        assert_eq!(state.status_line(), " INSERT | file.txt [+]    1:5 ");
+
+        type_keys(&mut state, &[Key::Escape, Key::Char('q'), Key::Char('w')]);
+        state.window_size.col = 42;
+        assert_eq!(
+            state.status_line(),
+            " NORMAL | file.txt [+] | recording @w 1:5 "
+        );
+    }
+
+    #[test]
+    fn file_info() {
+        let mut state = state_with_lines(&["first", "second", "third"]);
+        type_keys(&mut state, &[Key::Ctrl('g')]);
+        assert_eq!(state.message.msg, "\"[No Name]\" 3 lines --33%--");
+
+        state.save_file = Some("foo.rs".into());
+        type_keys(
+            &mut state,
+            &[Key::Char('G'), Key::Char('x'), Key::Ctrl('g')],
+        );
+        assert_eq!(state.message.msg, "\"foo.rs\" [Modified] 3 lines --100%--");
+
+        let state = state_with_lines(&[]);
+        assert_eq!(state.file_info(), "\"[No Name]\" 1 line --100%--");
+    }
+
+    #[test]
+    fn tab_in_insertion() {
+        let mut state = state_with_lines(&["ab"]);
+        type_keys(&mut state, &[Key::Char('A'), Key::Tab, Key::Tab]);
+        assert_eq!(state.cursor_pos.col, 8);
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["ab      "]);
+
+        let mut state = state_with_lines(&["ab"]);
+        state.config.expand_tab = false;
+        type_keys(&mut state, &[Key::Char('A'), Key::Tab, Key::Escape]);
+        assert_eq!(lines(&state), ["ab\t"]);
+        assert_eq!(state.cursor_pos.col, 3);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{Message, MessageType, State};
+
+impl State {
+    /// Keeps the marks on their text after the lines `start..start + removed` were replaced
+    /// by `added` lines, the marks on removed lines are deleted
+    pub fn adjust_marks(&mut self, start: usize, removed: usize, added: usize) {
+        self.marks
+            .retain(|_, (line, _)| !(start..start + removed).contains(line));
+        for (line, _) in self.marks.values_mut() {
+            if *line >= start + removed {
+                *line = *line + added - removed;
+            }
+        }
+    }
+
+    /// Moves the marks of line `from`, which was appended to line `into` at `col`
+    pub fn join_marks(&mut self, from: usize, into: usize, col: usize) {
+        for (line, mark_col) in self.marks.values_mut() {
+            if *line == from {
+                *line = into;
+                *mark_col += col;
+            }
+        }
+        self.adjust_marks(from, 1, 0);
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        let pos = (self.current_line_index(), self.cursor_pos.col);
+        self.marks.insert(name, pos);
+    }
+
+    /// Jumps to the mark `name`, or to the first non-blank of its line with `line_only`
+    pub fn jump_to_mark(&mut self, name: char, line_only: bool) {
+        let Some(&(line, col)) = self.marks.get(&name) else {
+            self.message = Message {
+                msg: "Mark not set".to_owned(),
+                r#type: MessageType::Error,
+            };
+            return;
+        };
+
+        self.push_jump();
+        self.move_to_line(line);
+        self.target_col = if line_only {
+            self.get_current_line().map_or(0, |l| l.first_non_blank())
+        } else {
+            col
+        };
+        // Undoing can leave marks past the end of the buffer
+        self.clamp_col_to_current_line();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{keys, state_with_lines, type_keys},
+    };
+
+    fn position(state: &crate::State) -> (usize, usize) {
+        (state.current_line_index(), state.cursor_pos.col)
+    }
+
+    #[test]
+    fn set_and_jump() {
+        let mut state = state_with_lines(&["one", "  two", "three"]);
+        type_keys(&mut state, &keys("jllllmaG`a"));
+        assert_eq!(position(&state), (1, 4));
+
+        type_keys(&mut state, &keys("G'a"));
+        assert_eq!(position(&state), (1, 2));
+
+        // Jumping to a mark is a jump
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(position(&state), (2, 0));
+
+        type_keys(&mut state, &keys("`b"));
+        assert_eq!(position(&state), (2, 0));
+        assert_eq!(state.message.msg, "Mark not set");
+    }
+
+    #[test]
+    fn marks_follow_lines() {
+        let mut state = state_with_lines(&["one", "two", "three", "four"]);
+        type_keys(&mut state, &keys("jmajjmbggOzero"));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(state.marks.get(&'a'), Some(&(2, 0)));
+        assert_eq!(state.marks.get(&'b'), Some(&(4, 0)));
+
+        type_keys(&mut state, &keys("jddyyp"));
+        assert_eq!(state.marks.get(&'a'), Some(&(1, 0)));
+        assert_eq!(state.marks.get(&'b'), Some(&(4, 0)));
+
+        // Deleting the marked line deletes the mark
+        type_keys(&mut state, &keys("`add"));
+        assert_eq!(state.marks.get(&'a'), None);
+        assert_eq!(state.marks.get(&'b'), Some(&(3, 0)));
+
+        // Joined lines keep their marks
+        type_keys(&mut state, &keys("kJ"));
+        assert_eq!(state.marks.get(&'b'), Some(&(2, 0)));
+        type_keys(&mut state, &keys("J"));
+        assert_eq!(state.marks.get(&'b'), Some(&(1, 0)));
+        type_keys(&mut state, &keys("J"));
+        assert_eq!(state.marks.get(&'b'), Some(&(0, 15)));
+    }
+}
@@ -0,0 +1,321 @@
+use log::debug;
+
+use crate::{State, edit::Position, keymap::Action, register::RegisterContent};
+
+impl State {
+    /// Returns the range covered by the motion `action` from the cursor, ends included
+    /// The cursor is left where it was, motions leaving the line give no range
+    /// except `w` which stops at the end of the line like in Vim, and `%` which covers
+    /// the text between the brackets
+    pub fn motion_range(
+        &mut self,
+        action: Action,
+        count: Option<usize>,
+    ) -> Option<(Position, Position)> {
+        // A jump motion that gives nothing to delete does not leave a jump behind
+        let jumps = self.jumps.clone();
+        let range = self.probe_motion(action, count);
+        if range.is_none() {
+            self.jumps = jumps;
+        }
+
+        range
+    }
+
+    /// Runs the motion to find the range it covers, then puts the cursor back
+    fn probe_motion(
+        &mut self,
+        action: Action,
+        count: Option<usize>,
+    ) -> Option<(Position, Position)> {
+        let current = self.current_line_index();
+        let (col, target_col) = (self.cursor_pos.col, self.target_col);
+        let text_offset = self.text_offset;
+
+        // `w` running out of words goes to the end of the line
+        let (is_motion, out_of_words) = if action == Action::WordForward {
+            let count = count.unwrap_or(1);
+            let moved = (0..count).take_while(|_| self.move_word_forward()).count();
+            (true, moved < count)
+        } else {
+            (self.handle_motion(action, count), false)
+        };
+        let target = (self.current_line_index(), self.cursor_pos.col);
+
+        // The view does not scroll when nothing is deleted
+        self.text_offset = text_offset;
+        self.cursor_pos.row = current - text_offset;
+        self.cursor_pos.col = col;
+        self.target_col = target_col;
+        // `%` stays in place without a matching bracket
+        if !is_motion || (action == Action::MatchingBracket && target == (current, col)) {
+            return None;
+        }
+        if action == Action::MatchingBracket && target.0 != current {
+            return Some(((current, col).min(target), (current, col).max(target)));
+        }
+
+        let line = &self.text_lines[current];
+        let len = line.len();
+        let inclusive = matches!(
+            action,
+            Action::WordEnd | Action::LineEnd | Action::MatchingBracket
+        );
+        let end = match target {
+            _ if out_of_words => len,
+            (line, end) if line == current => end,
+            _ if action == Action::WordForward => len,
+            _ => return None,
+        };
+
+        // `%` can go backward, the character under the cursor is then included
+        // An included composed character goes with its combining characters
+        let to = if inclusive {
+            line.next_grapheme(col.max(end))
+        } else {
+            col.max(end)
+        };
+        let (from, to) = (col.min(end), to.min(len));
+        if from >= to {
+            return None;
+        }
+
+        Some(((current, from), (current, to - 1)))
+    }
+
+    /// Deletes the text covered by the motion `action` and saves it in the register
+    /// Returns the position of the deleted text
+    pub fn delete_motion(&mut self, action: Action, count: Option<usize>) -> Option<Position> {
+        let Some((start, end)) = self.motion_range(action, count) else {
+            debug!("No range to delete for {action:?}");
+            return None;
+        };
+
+        self.save_undo_point();
+        let text = self.delete_range(start, end);
+        self.set_register(RegisterContent::Chars(text));
+
+        // `%` may delete backward from another line
+        self.move_to_line(start.0);
+        let len = self.text_lines[start.0].len();
+        self.cursor_pos.col = start.1.min(len.saturating_sub(1));
+        self.target_col = self.cursor_pos.col;
+
+        Some(start)
+    }
+
+    /// Deletes the text covered by the motion `action` and enters insertion mode in its place
+    /// Like in Vim, `cw` on a word only changes up to the end of the word
+    pub fn change_motion(&mut self, action: Action, count: Option<usize>) {
+        let on_word = self
+            .get_current_line()
+            .and_then(|line| line.chars().nth(self.cursor_pos.col))
+            .is_some_and(|c| !c.is_whitespace());
+        let action = match action {
+            Action::WordForward if on_word => Action::WordEnd,
+            action => action,
+        };
+
+        let col = if let Some((_, col)) = self.delete_motion(action, count) {
+            col
+        } else if action == Action::LineEnd {
+            // Nothing to delete at the end of the line but this still starts inserting
+            self.save_undo_point();
+            self.cursor_pos.col
+        } else {
+            return;
+        };
+
+        self.cursor_pos.col = col;
+        self.target_col = col;
+        self.enable_insertion_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        register::RegisterContent,
+        test_utils::{keys, lines, numbered_lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn delete_word() {
+        let mut state = state_with_lines(&["foo bar.baz", "next"]);
+        type_keys(&mut state, &[Key::Char('d'), Key::Char('w')]);
+        assert_eq!(lines(&state), ["bar.baz", "next"]);
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "foo "));
+
+        type_keys(&mut state, &[Key::Char('d'), Key::Char('e')]);
+        assert_eq!(lines(&state), [".baz", "next"]);
+
+        type_keys(
+            &mut state,
+            &[Key::Char('l'), Key::Char('d'), Key::Char('w')],
+        );
+        assert_eq!(lines(&state), [".", "next"]);
+        assert_eq!(state.cursor_pos.col, 0);
+
+        type_keys(&mut state, &[Key::Char('u'), Key::Char('u')]);
+        assert_eq!(lines(&state), ["bar.baz", "next"]);
+    }
+
+    #[test]
+    fn no_jump_without_deletion() {
+        let mut state = numbered_lines(10);
+        type_keys(&mut state, &keys("5jdGj"));
+        assert_eq!(lines(&state).len(), 10);
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!(state.current_line_index(), 6);
+    }
+
+    #[test]
+    fn delete_last_word() {
+        let mut state = state_with_lines(&["foo"]);
+        type_keys(&mut state, &keys("dw"));
+        assert_eq!(lines(&state), [""]);
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "foo"));
+
+        let mut state = state_with_lines(&["a b c d"]);
+        type_keys(&mut state, &keys("d2w."));
+        assert_eq!(lines(&state), [""]);
+    }
+
+    #[test]
+    fn delete_to_end_of_line() {
+        let mut state = state_with_lines(&["foo bar baz", "next"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('w'), Key::Char('d'), Key::Char('$')],
+        );
+        assert_eq!(lines(&state), ["foo ", "next"]);
+        assert_eq!(state.cursor_pos.col, 3);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('0'),
+                Key::Char('2'),
+                Key::Char('d'),
+                Key::Char('e'),
+            ],
+        );
+        assert_eq!(lines(&state), ["foo ", "next"]);
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('d'),
+                Key::Char('j'),
+                Key::Char('d'),
+                Key::Char('l'),
+            ],
+        );
+        assert_eq!(lines(&state), ["oo ", "next"]);
+    }
+
+    #[test]
+    fn delete_to_matching_bracket() {
+        let mut state = state_with_lines(&["f(a) {", "  x", "} end"]);
+        type_keys(&mut state, &keys("ld%"));
+        assert_eq!(lines(&state), ["f {", "  x", "} end"]);
+
+        type_keys(&mut state, &keys("$d%"));
+        assert_eq!(lines(&state), ["f  end"]);
+        assert!(
+            matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "{\n  x\n}")
+        );
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 2));
+
+        // Backward from the closing bracket
+        type_keys(&mut state, &keys("u"));
+        type_keys(&mut state, &keys("jj0c%x"));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["f x end"]);
+
+        // No bracket, nothing is deleted
+        type_keys(&mut state, &keys("0d%"));
+        assert_eq!(lines(&state), ["f x end"]);
+    }
+
+    #[test]
+    fn motion_count() {
+        let mut state = state_with_lines(&["a b c d e f g h i j k l m n o p q r s t u"]);
+        type_keys(&mut state, &keys("d2w"));
+        assert_eq!(lines(&state), ["c d e f g h i j k l m n o p q r s t u"]);
+        type_keys(&mut state, &keys("2d2w"));
+        assert_eq!(lines(&state), ["g h i j k l m n o p q r s t u"]);
+        type_keys(&mut state, &keys("d12w"));
+        assert_eq!(lines(&state), ["s t u"]);
+
+        let mut state = state_with_lines(&["foo bar baz"]);
+        type_keys(&mut state, &keys("c2ex"));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["x baz"]);
+        // `0` is still a motion
+        type_keys(&mut state, &keys("$d0"));
+        assert_eq!(lines(&state), ["z"]);
+    }
+
+    #[test]
+    fn motion_does_not_scroll() {
+        let mut state = numbered_lines(50);
+        type_keys(&mut state, &keys("dG"));
+        assert_eq!(state.text_offset, 0);
+        type_keys(
+            &mut state,
+            &[Key::Ctrl('f'), Key::Char('d'), Key::Ctrl('f')],
+        );
+        let text_offset = state.text_offset;
+        type_keys(&mut state, &keys("dG"));
+        assert_eq!(state.text_offset, text_offset);
+        assert_eq!(state.text_lines.len(), 50);
+    }
+
+    #[test]
+    fn change_word() {
+        let mut state = state_with_lines(&["foo bar baz"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('c'), Key::Char('w'), Key::Char('x'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["x bar baz"]);
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "foo"));
+
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('w'),
+                Key::Char('2'),
+                Key::Char('c'),
+                Key::Char('w'),
+                Key::Char('y'),
+                Key::Escape,
+            ],
+        );
+        assert_eq!(lines(&state), ["x y"]);
+
+        type_keys(&mut state, &[Key::Char('u')]);
+        assert_eq!(lines(&state), ["x bar baz"]);
+    }
+
+    #[test]
+    fn change_to_end_of_line() {
+        let mut state = state_with_lines(&["foo bar", ""]);
+        type_keys(
+            &mut state,
+            &[Key::Char('w'), Key::Char('C'), Key::Char('!'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["foo !", ""]);
+
+        type_keys(
+            &mut state,
+            &[Key::Char('j'), Key::Char('C'), Key::Char('?'), Key::Escape],
+        );
+        assert_eq!(lines(&state), ["foo !", "?"]);
+
+        type_keys(&mut state, &[Key::Char('u'), Key::Char('u')]);
+        assert_eq!(lines(&state), ["foo bar", ""]);
+    }
+}
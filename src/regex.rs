@@ -0,0 +1,631 @@
+use std::{error::Error, fmt::Display, ops::Range};
+
+/// Regular expressions for search and substitute, matched against the characters of a line
+/// This is a subset of the usual syntax: `.`, `[...]`, `\d`, `\w`, `\s`,
+/// `^`, `$`, `\b`, groups with `|`, and the `*`, `+`, `?` and `{m,n}` quantifiers
+/// with their lazy `?` forms
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    WordBoundary,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Repeat>),
+}
+
+#[derive(Debug, Clone)]
+struct Repeat {
+    node: Node,
+    min: usize,
+    max: Option<usize>,
+    greedy: bool,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+    /// `\D`, `\W` or `\S`
+    Not(Box<ClassItem>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegexError {
+    UnmatchedParenthesis,
+    UnclosedClass,
+    NothingToRepeat,
+    TrailingBackslash,
+    /// Repetitions would make more than `MAX_PROGRAM_LEN` instructions
+    TooLarge,
+}
+
+impl Error for RegexError {}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegexError::UnmatchedParenthesis => write!(f, "Unmatched parenthesis"),
+            RegexError::UnclosedClass => write!(f, "Missing ] after ["),
+            RegexError::NothingToRepeat => write!(f, "Nothing to repeat"),
+            RegexError::TrailingBackslash => write!(f, "Trailing backslash"),
+            RegexError::TooLarge => write!(f, "Pattern too large"),
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the class of `\d`, `\w`, `\s` and their uppercase negations
+fn named_class(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit),
+        'w' => Some(ClassItem::Word),
+        's' => Some(ClassItem::Space),
+        'D' | 'W' | 'S' => Some(ClassItem::Not(Box::new(named_class(
+            c.to_ascii_lowercase(),
+        )?))),
+        _ => None,
+    }
+}
+
+/// Character written as `\c`, like `\n` or `\.`
+const fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        c => c,
+    }
+}
+
+impl ClassItem {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(first, last) => (*first..=*last).contains(&c),
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => is_word(c),
+            ClassItem::Space => c.is_whitespace(),
+            ClassItem::Not(item) => !item.contains(c),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Parses alternatives separated by `|` until the end or a `)` when `nested`
+    fn alternatives(&mut self, nested: bool) -> Result<Vec<Vec<Node>>, RegexError> {
+        let mut alternatives = vec![Vec::new()];
+        loop {
+            let sequence = alternatives
+                .last_mut()
+                .expect("there is always an alternative");
+            let Some(c) = self.chars.next() else {
+                return if nested {
+                    Err(RegexError::UnmatchedParenthesis)
+                } else {
+                    Ok(alternatives)
+                };
+            };
+
+            let node = match c {
+                '|' => {
+                    alternatives.push(Vec::new());
+                    continue;
+                }
+                ')' if nested => return Ok(alternatives),
+                ')' => return Err(RegexError::UnmatchedParenthesis),
+                '(' => Node::Group(self.alternatives(true)?),
+                '[' => self.class()?,
+                '.' => Node::Any,
+                '^' => Node::Start,
+                '$' => Node::End,
+                '\\' => {
+                    let escaped = self.chars.next().ok_or(RegexError::TrailingBackslash)?;
+                    match (escaped, named_class(escaped)) {
+                        ('b', _) => Node::WordBoundary,
+                        (_, Some(item)) => Node::Class {
+                            items: vec![item],
+                            negated: false,
+                        },
+                        (escaped, None) => Node::Char(escaped_char(escaped)),
+                    }
+                }
+                '*' | '+' | '?' => {
+                    let node = sequence.pop().ok_or(RegexError::NothingToRepeat)?;
+                    let (min, max) = match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    };
+                    self.repeat(node, min, max)?
+                }
+                '{' => match self.bounds() {
+                    Some((min, max)) => {
+                        let node = sequence.pop().ok_or(RegexError::NothingToRepeat)?;
+                        self.repeat(node, min, max)?
+                    }
+                    // Not a quantifier
+                    None => Node::Char('{'),
+                },
+                c => Node::Char(c),
+            };
+            sequence.push(node);
+        }
+    }
+
+    fn repeat(&mut self, node: Node, min: usize, max: Option<usize>) -> Result<Node, RegexError> {
+        if matches!(node, Node::Repeat(_)) {
+            return Err(RegexError::NothingToRepeat);
+        }
+        let greedy = self.chars.next_if_eq(&'?').is_none();
+
+        Ok(Node::Repeat(Box::new(Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        })))
+    }
+
+    /// Parses the `m}`, `m,}` or `m,n}` following a `{`, nothing is consumed on failure
+    fn bounds(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest: String = self.chars.clone().take_while(|&c| c != '}').collect();
+        if self.chars.clone().nth(rest.chars().count()) != Some('}') {
+            return None;
+        }
+
+        let bounds = match rest.split_once(',') {
+            None => {
+                let n = rest.parse().ok()?;
+                (n, Some(n))
+            }
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+        };
+        for _ in 0..=rest.chars().count() {
+            self.chars.next();
+        }
+
+        Some(bounds)
+    }
+
+    /// Parses what follows a `[`, a `]` right after it is taken literally
+    fn class(&mut self) -> Result<Node, RegexError> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.chars.next().ok_or(RegexError::UnclosedClass)?;
+            let c = match c {
+                ']' if !first => break,
+                '\\' => {
+                    let escaped = self.chars.next().ok_or(RegexError::UnclosedClass)?;
+                    if let Some(item) = named_class(escaped) {
+                        items.push(item);
+                        first = false;
+                        continue;
+                    }
+                    escaped_char(escaped)
+                }
+                c => c,
+            };
+            first = false;
+
+            // A `-` at the end is literal
+            let last = match self.chars.clone().nth(1) {
+                Some(next) if next != ']' && self.chars.next_if_eq(&'-').is_some() => {
+                    let next = self.chars.next().ok_or(RegexError::UnclosedClass)?;
+                    if next == '\\' {
+                        escaped_char(self.chars.next().ok_or(RegexError::UnclosedClass)?)
+                    } else {
+                        next
+                    }
+                }
+                _ => c,
+            };
+            items.push(ClassItem::Range(c, last));
+        }
+
+        Ok(Node::Class { items, negated })
+    }
+}
+
+/// Instructions a pattern is compiled to
+#[derive(Debug)]
+enum Inst {
+    /// A node matching one character
+    Char(Node),
+    /// A node matching no character, like `^` or `\b`
+    Assert(Node),
+    /// Continues at both, the first one is preferred
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Limit on the size of compiled patterns, repetitions like `a{100000}` copy their node
+const MAX_PROGRAM_LEN: usize = 100_000;
+
+#[derive(Default)]
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn alternatives(&mut self, alternatives: &[Vec<Node>]) {
+        let mut jumps = Vec::new();
+        for (i, sequence) in alternatives.iter().enumerate() {
+            let split = (i + 1 < alternatives.len()).then(|| self.push(Inst::Split(0, 0)));
+            for node in sequence {
+                self.node(node);
+            }
+            if let Some(split) = split {
+                jumps.push(self.push(Inst::Jump(0)));
+                self.program[split] = Inst::Split(split + 1, self.program.len());
+            }
+        }
+        for jump in jumps {
+            self.program[jump] = Inst::Jump(self.program.len());
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Group(alternatives) => self.alternatives(alternatives),
+            Node::Repeat(repeat) => self.repeat(repeat),
+            Node::Start | Node::End | Node::WordBoundary => {
+                self.push(Inst::Assert(node.clone()));
+            }
+            node => {
+                self.push(Inst::Char(node.clone()));
+            }
+        }
+    }
+
+    fn too_large(&self) -> bool {
+        self.program.len() > MAX_PROGRAM_LEN
+    }
+
+    /// The node is copied for every repetition, optional ones can skip to the end
+    /// Copying stops once the program is too large
+    fn repeat(&mut self, repeat: &Repeat) {
+        for _ in 0..repeat.min {
+            if self.too_large() {
+                return;
+            }
+            self.node(&repeat.node);
+        }
+        let split = |next, end| {
+            if repeat.greedy {
+                Inst::Split(next, end)
+            } else {
+                Inst::Split(end, next)
+            }
+        };
+
+        if let Some(max) = repeat.max {
+            let mut splits = Vec::new();
+            for _ in repeat.min..max {
+                if self.too_large() {
+                    return;
+                }
+                splits.push(self.push(Inst::Split(0, 0)));
+                self.node(&repeat.node);
+            }
+            let end = self.program.len();
+            for split_at in splits {
+                self.program[split_at] = split(split_at + 1, end);
+            }
+        } else {
+            let start = self.push(Inst::Split(0, 0));
+            self.node(&repeat.node);
+            self.push(Inst::Jump(start));
+            self.program[start] = split(start + 1, self.program.len());
+        }
+    }
+}
+
+/// Matching states at one position, in order of preference, with where their match started
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            list: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+/// Characters being matched by `regex`
+struct Matcher<'a> {
+    regex: &'a Regex,
+    chars: &'a [char],
+}
+
+impl Matcher<'_> {
+    fn same_char(&self, a: char, b: char) -> bool {
+        a == b || (self.regex.ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn class_contains(&self, items: &[ClassItem], c: char) -> bool {
+        let contains = |c| items.iter().any(|item| item.contains(c));
+        contains(c)
+            || (self.regex.ignore_case && c.to_lowercase().chain(c.to_uppercase()).any(contains))
+    }
+
+    /// Returns where a node matching at most one character ends if it matches at `pos`
+    fn step(&self, node: &Node, pos: usize) -> Option<usize> {
+        let c = self.chars.get(pos).copied();
+        let matched = match node {
+            Node::Char(expected) => c.is_some_and(|c| self.same_char(c, *expected)),
+            Node::Any => c.is_some(),
+            Node::Class { items, negated } => {
+                c.is_some_and(|c| self.class_contains(items, c) != *negated)
+            }
+            Node::Start => return (pos == 0).then_some(pos),
+            Node::End => return (pos == self.chars.len()).then_some(pos),
+            Node::WordBoundary => {
+                let before = pos > 0 && is_word(self.chars[pos - 1]);
+                let after = c.is_some_and(is_word);
+                return (before != after).then_some(pos);
+            }
+            Node::Group(_) | Node::Repeat(_) => unreachable!("not a single character"),
+        };
+
+        matched.then_some(pos + 1)
+    }
+
+    /// Adds the thread at `pc` to `threads` after following the instructions that do not
+    /// consume a character, each instruction is only visited once per position
+    /// The instructions left to follow are kept on a stack, in order of preference
+    fn add(&self, threads: &mut Threads, pc: usize, start: usize, pos: usize) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if std::mem::replace(&mut threads.seen[pc], true) {
+                continue;
+            }
+            match &self.regex.program[pc] {
+                Inst::Jump(to) => stack.push(*to),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Assert(node) => {
+                    if self.step(node, pos).is_some() {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::Char(_) | Inst::Match => threads.list.push((pc, start)),
+            }
+        }
+    }
+}
+
+impl Regex {
+    /// Letters match whatever their case with `ignore_case`
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().peekable(),
+        };
+        let mut compiler = Compiler::default();
+        compiler.alternatives(&parser.alternatives(false)?);
+        if compiler.too_large() {
+            return Err(RegexError::TooLarge);
+        }
+        compiler.push(Inst::Match);
+
+        Ok(Regex {
+            program: compiler.program,
+            ignore_case,
+        })
+    }
+
+    /// Returns the codepoint range of the leftmost match starting at or after `from`
+    /// All the ways to match are followed at once, in order of preference, so the time
+    /// taken only grows with the length of `chars` times the size of the pattern
+    #[must_use]
+    pub fn find_at(&self, chars: &[char], from: usize) -> Option<Range<usize>> {
+        let matcher = Matcher { regex: self, chars };
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found = None;
+
+        for pos in from..=chars.len() {
+            // Matches starting further are only tried while none was found
+            if found.is_none() {
+                matcher.add(&mut current, 0, pos, pos);
+            } else if current.list.is_empty() {
+                break;
+            }
+
+            for &(pc, start) in &current.list {
+                match &self.program[pc] {
+                    Inst::Match => {
+                        // The threads left are less preferred
+                        found = Some(start..pos);
+                        break;
+                    }
+                    Inst::Char(node) => {
+                        if matcher.step(node, pos).is_some() {
+                            matcher.add(&mut next, pc + 1, start, pos + 1);
+                        }
+                    }
+                    _ => unreachable!("only kept when consuming a character"),
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::regex::{Regex, RegexError};
+
+    fn find(pattern: &str, text: &str) -> Option<Range<usize>> {
+        let chars: Vec<char> = text.chars().collect();
+        Regex::new(pattern, false).unwrap().find_at(&chars, 0)
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(find("bar", "foo bar"), Some(4..7));
+        assert_eq!(find("日本", "a日本"), Some(1..3));
+        assert_eq!(find(r"a\.b", "axb a.b"), Some(4..7));
+        assert_eq!(find("{x}", "a{x}"), Some(1..4));
+        assert_eq!(find("baz", "foo bar"), None);
+        assert_eq!(find("", "foo"), Some(0..0));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find("[a-c]+", "xxbcay"), Some(2..5));
+        assert_eq!(find("[^a-c ]", "ab c!"), Some(4..5));
+        assert_eq!(find(r"\d+", "abc 123"), Some(4..7));
+        assert_eq!(find(r"\w+", " é_1 "), Some(1..4));
+        assert_eq!(find(r"[\s-]", "ab-c d"), Some(2..3));
+        assert_eq!(find(r"\S\s\S", "a  b c"), Some(3..6));
+        assert_eq!(find("[]a]", "b]"), Some(1..2));
+        assert_eq!(find("[a-]", "b-"), Some(1..2));
+        assert_eq!(find("h.llo", "hello"), Some(0..5));
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert_eq!(find("ab*c", "ac abbbc"), Some(0..2));
+        assert_eq!(find("ab+c", "ac abbbc"), Some(3..8));
+        assert_eq!(find("colou?r", "color"), Some(0..5));
+        assert_eq!(find("a{2}", "a aaa"), Some(2..4));
+        assert_eq!(find("a{2,}", "a aaa"), Some(2..5));
+        assert_eq!(find("a{1,2}", "aaa"), Some(0..2));
+        assert_eq!(find("<.*>", "<a> <b>"), Some(0..7));
+        assert_eq!(find("<.*?>", "<a> <b>"), Some(0..3));
+        // Backtracking into a repetition
+        assert_eq!(find("a.*b", "a b c"), Some(0..3));
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(find("cat|dog", "hotdog"), Some(3..6));
+        assert_eq!(find("(ab)+", "xababa"), Some(1..5));
+        assert_eq!(find("a(b|c)*d", "abcbd"), Some(0..5));
+        assert_eq!(find("(a|ab)c", "abc"), Some(0..3));
+        assert_eq!(find("(a*)*b", "aab"), Some(0..3));
+        assert_eq!(find("(x?)+y", "y"), Some(0..1));
+        assert_eq!(find("(a|ab)(c|bcd)", "abcd"), Some(0..4));
+        assert_eq!(find("(a+?)(b|a)", "aab"), Some(0..2));
+    }
+
+    #[test]
+    fn no_exponential_backtracking() {
+        let text = "a".repeat(200);
+        assert_eq!(find("(a|a)*b", &text), None);
+        assert_eq!(find("(a*)*b", &text), None);
+        assert_eq!(find("(a|aa)+$", &text), Some(0..200));
+        // Long chains of optional repetitions do not recurse
+        assert_eq!(find("(a?){0,20000}b", "aab"), Some(0..3));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(find("^foo", "foo foo"), Some(0..3));
+        assert_eq!(find("foo$", "foo foo"), Some(4..7));
+        assert_eq!(find("^$", ""), Some(0..0));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find(r"\bin\b", "inside in"), Some(7..9));
+    }
+
+    #[test]
+    fn find_from() {
+        let chars: Vec<char> = "foo foo".chars().collect();
+        let regex = Regex::new("o+", false).unwrap();
+        assert_eq!(regex.find_at(&chars, 0), Some(1..3));
+        assert_eq!(regex.find_at(&chars, 2), Some(2..3));
+        assert_eq!(regex.find_at(&chars, 3), Some(5..7));
+        assert_eq!(regex.find_at(&chars, 8), None);
+    }
+
+    #[test]
+    fn ignore_case() {
+        let chars: Vec<char> = "Foo ÉTÉ".chars().collect();
+        let regex = Regex::new("[a-z]+ été", true).unwrap();
+        assert_eq!(regex.find_at(&chars, 0), Some(0..7));
+        assert_eq!(
+            Regex::new("É", false).unwrap().find_at(&chars, 5),
+            Some(6..7)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Regex::new("(ab", false).unwrap_err(),
+            RegexError::UnmatchedParenthesis
+        );
+        assert_eq!(
+            Regex::new("ab)", false).unwrap_err(),
+            RegexError::UnmatchedParenthesis
+        );
+        assert_eq!(
+            Regex::new("[ab", false).unwrap_err(),
+            RegexError::UnclosedClass
+        );
+        assert_eq!(
+            Regex::new("*a", false).unwrap_err(),
+            RegexError::NothingToRepeat
+        );
+        assert_eq!(
+            Regex::new("a**", false).unwrap_err(),
+            RegexError::NothingToRepeat
+        );
+        assert_eq!(
+            Regex::new("a\\", false).unwrap_err(),
+            RegexError::TrailingBackslash
+        );
+        assert_eq!(
+            Regex::new("a{4000000000}", false).unwrap_err(),
+            RegexError::TooLarge
+        );
+        assert_eq!(
+            Regex::new("((a{1000}){1000}){1000}", false).unwrap_err(),
+            RegexError::TooLarge
+        );
+    }
+}
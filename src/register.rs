@@ -0,0 +1,187 @@
+use std::collections::hash_map::Entry;
+
+use crate::{State, line::Line};
+
+#[derive(Debug, Clone)]
+pub enum RegisterContent {
+    /// Whole lines, pasted below or above the cursor line
+    Lines(Vec<Line>),
+    /// Text pasted after or before the cursor, may contain newlines
+    Chars(String),
+}
+
+impl RegisterContent {
+    /// Adds `other` at the end, the result is line-wise if either of them is
+    fn append(&mut self, other: RegisterContent) {
+        match (&mut *self, other) {
+            (RegisterContent::Chars(text), RegisterContent::Chars(other)) => text.push_str(&other),
+            (RegisterContent::Lines(lines), other) => lines.extend(other.into_lines()),
+            (RegisterContent::Chars(_), RegisterContent::Lines(other)) => {
+                let mut lines =
+                    std::mem::replace(self, RegisterContent::Chars(String::new())).into_lines();
+                lines.extend(other);
+                *self = RegisterContent::Lines(lines);
+            }
+        }
+    }
+
+    fn into_lines(self) -> Vec<Line> {
+        match self {
+            RegisterContent::Lines(lines) => lines,
+            RegisterContent::Chars(text) => text.split('\n').map(Line::from).collect(),
+        }
+    }
+}
+
+impl State {
+    /// Saves yanked or deleted text in the unnamed register and in the selected one, if any
+    /// An uppercase register name appends to the lowercase register
+    pub fn set_register(&mut self, content: RegisterContent) {
+        if let Some(name @ ('a'..='z' | 'A'..='Z')) = self.selected_register.take() {
+            let named = self.named_registers.entry(name.to_ascii_lowercase());
+            match named {
+                Entry::Occupied(mut entry) if name.is_ascii_uppercase() => {
+                    entry.get_mut().append(content);
+                    self.register = Some(entry.get().clone());
+                    return;
+                }
+                named => {
+                    named.insert_entry(content.clone());
+                }
+            }
+        }
+
+        self.register = Some(content);
+    }
+
+    /// Pastes the register after the cursor, or before it if `after` is false
+    pub fn paste(&mut self, after: bool) {
+        let content = match self.selected_register.take() {
+            Some(name @ ('a'..='z' | 'A'..='Z')) => self
+                .named_registers
+                .get(&name.to_ascii_lowercase())
+                .cloned(),
+            _ => self.register.clone(),
+        };
+        let Some(content) = content else {
+            return;
+        };
+        self.save_undo_point();
+
+        let current = self.current_line_index();
+        match content {
+            RegisterContent::Lines(lines) => {
+                let index = if after { current + 1 } else { current };
+                self.adjust_marks(index, 0, lines.len());
+                self.text_lines.splice(index..index, lines);
+                self.dirty = true;
+                self.move_to_line(index);
+                self.cursor_pos.col = 0;
+            }
+            RegisterContent::Chars(text) => {
+                let line = &self.text_lines[current];
+                let len = line.len();
+                // Pasting after a composed character keeps its combining characters
+                let col = if after {
+                    line.next_grapheme(self.cursor_pos.col)
+                } else {
+                    self.cursor_pos.col.min(len)
+                };
+
+                // The cursor ends on the last pasted character, or on the first one when
+                // the text spans several lines like in Vim
+                let (row, end) = self.insert_text((current, col), &text);
+                if row == current {
+                    self.cursor_pos.col = end.saturating_sub(1);
+                } else {
+                    self.cursor_pos.col = col;
+                }
+            }
+        }
+        self.target_col = self.cursor_pos.col;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        register::RegisterContent,
+        test_utils::{keys, lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn named_registers() {
+        let mut state = state_with_lines(&["first", "second", "third"]);
+        type_keys(&mut state, &keys("\"ayyjdd"));
+        assert_eq!(lines(&state), ["first", "third"]);
+
+        // The unnamed register has the last delete, "a the yank
+        type_keys(&mut state, &keys("p\"ap"));
+        assert_eq!(lines(&state), ["first", "third", "second", "first"]);
+
+        // The selection only lasts for one command
+        type_keys(&mut state, &keys("\"bjdd\"bp"));
+        assert_eq!(lines(&state), ["first", "third", "second"]);
+
+        // Empty register
+        type_keys(&mut state, &keys("\"zp"));
+        assert_eq!(lines(&state), ["first", "third", "second"]);
+    }
+
+    #[test]
+    fn delete_char_register() {
+        let mut state = state_with_lines(&["abcd"]);
+        type_keys(&mut state, &keys("\"axx"));
+        assert_eq!(lines(&state), ["cd"]);
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "b"));
+
+        type_keys(&mut state, &keys("\"ap"));
+        assert_eq!(lines(&state), ["cad"]);
+        type_keys(&mut state, &keys("0xp"));
+        assert_eq!(lines(&state), ["acd"]);
+    }
+
+    #[test]
+    fn paste_after_composed_character() {
+        let mut state = state_with_lines(&["e\u{301}b"]);
+        type_keys(&mut state, &keys("$xp"));
+        assert_eq!(lines(&state), ["e\u{301}b"]);
+        type_keys(&mut state, &keys("0p"));
+        assert_eq!(lines(&state), ["e\u{301}bb"]);
+    }
+
+    #[test]
+    fn append_to_register() {
+        let mut state = state_with_lines(&["one two", "three"]);
+        type_keys(&mut state, &keys("\"adw\"Ade"));
+        assert!(matches!(
+            state.named_registers.get(&'a'),
+            Some(RegisterContent::Chars(text)) if text == "one two"
+        ));
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "one two"));
+
+        type_keys(&mut state, &keys("j\"Ayy"));
+        assert!(matches!(
+            state.named_registers.get(&'a'),
+            Some(RegisterContent::Lines(lines)) if lines.len() == 2 && lines[1].as_str() == "three"
+        ));
+
+        type_keys(&mut state, &keys("gg2\"add"));
+        assert_eq!(lines(&state), [""]);
+        assert!(matches!(
+            state.named_registers.get(&'a'),
+            Some(RegisterContent::Lines(lines)) if lines.len() == 2 && lines[0].as_str() == ""
+        ));
+    }
+
+    #[test]
+    fn paste_several_lines_of_chars() {
+        let mut state = state_with_lines(&["a(b", "c)d"]);
+        type_keys(&mut state, &keys("ld%"));
+        assert_eq!(lines(&state), ["ad"]);
+
+        type_keys(&mut state, &keys("0p"));
+        assert_eq!(lines(&state), ["a(b", "c)d"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 1));
+    }
+}
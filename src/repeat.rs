@@ -0,0 +1,163 @@
+use crate::{Mode, State, key::Key};
+
+/// Remembers the keys of the last command that modified the buffer
+/// Replaying keys works the same for every command, be it `x`, an operator with
+/// its motion or a whole insertion session
+#[derive(Debug, Default)]
+pub struct ChangeRecorder {
+    /// Keys of the command being typed
+    keys: Vec<Key>,
+    /// Number of changes in the history when the command started
+    start: usize,
+    last_change: Vec<Key>,
+    /// Times the text of the insertion session being typed is inserted, e.g. `3i`
+    insertion_count: usize,
+    /// Position in `keys` where the text of the insertion session starts
+    insertion_start: usize,
+    /// The session started with `o` or `O`, every copy of the text goes on a new line
+    opened_line: bool,
+}
+
+impl State {
+    /// True when no command is being typed
+    fn is_between_commands(&self) -> bool {
+        matches!(self.current_mode, Mode::Normal)
+            && self.pending.is_none()
+            && self.pending_count.is_none()
+            && self.selected_register.is_none()
+    }
+
+    /// Must be called with every key before handling it
+    pub fn record_key(&mut self, key: &Key) {
+        if self.is_between_commands() {
+            self.recorder.keys.clear();
+            self.recorder.start = self.history.changes();
+        }
+        self.recorder.keys.push(key.clone());
+    }
+
+    /// Must be called after handling every key, the finished command is kept if it was a change
+    pub fn finish_recording(&mut self) {
+        // Opening a file resets the history, which is not a change
+        if self.is_between_commands() && self.history.changes() > self.recorder.start {
+            self.recorder.last_change.clone_from(&self.recorder.keys);
+            self.recorder.start = self.history.changes();
+        }
+    }
+
+    /// Replays the last change, `count` replaces the count it was typed with
+    pub fn repeat_last_change(&mut self, count: Option<usize>) {
+        let mut keys = self.recorder.last_change.clone();
+        if let Some(count) = count {
+            let typed_count = keys
+                .iter()
+                .take_while(|key| matches!(key, Key::Char('0'..='9')))
+                .count();
+            keys.splice(..typed_count, count.to_string().chars().map(Key::Char));
+        }
+
+        self.replay_keys(&keys);
+    }
+
+    /// Must be called when an insertion session starts, its text is typed `count` times in total
+    pub fn count_insertion(&mut self, count: Option<usize>, opened_line: bool) {
+        self.recorder.insertion_count = count.unwrap_or(1);
+        self.recorder.insertion_start = self.recorder.keys.len();
+        self.recorder.opened_line = opened_line;
+    }
+
+    /// Keys to type again for the insertion session ending with the key being handled,
+    /// empty if it has no count
+    pub fn take_insertion_repeat(&mut self) -> Vec<Key> {
+        let count = std::mem::take(&mut self.recorder.insertion_count);
+        let end = self.recorder.keys.len().saturating_sub(1);
+        let Some(typed) = self.recorder.keys.get(self.recorder.insertion_start..end) else {
+            return Vec::new();
+        };
+
+        let mut keys = Vec::new();
+        for _ in 1..count {
+            if self.recorder.opened_line {
+                keys.push(Key::Enter);
+            }
+            keys.extend_from_slice(typed);
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        test_utils::{keys, lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn repeat_delete() {
+        let mut state = state_with_lines(&["abcdef", "1", "2", "3", "4", "5"]);
+        // Nothing to repeat yet
+        type_keys(&mut state, &keys("."));
+        assert_eq!(lines(&state), ["abcdef", "1", "2", "3", "4", "5"]);
+
+        type_keys(&mut state, &keys("x.l."));
+        assert_eq!(lines(&state), ["cef", "1", "2", "3", "4", "5"]);
+
+        // Motions and undo are not changes
+        type_keys(&mut state, &keys("jdd.ujk."));
+        assert_eq!(lines(&state), ["cef", "3", "4", "5"]);
+
+        // Only one line is left to delete
+        type_keys(&mut state, &keys("j2dd."));
+        assert_eq!(lines(&state), ["cef"]);
+    }
+
+    #[test]
+    fn repeat_with_count() {
+        let mut state = state_with_lines(&["1", "2", "3", "4", "5", "6", "7"]);
+        type_keys(&mut state, &keys("2dd3."));
+        assert_eq!(lines(&state), ["6", "7"]);
+
+        // The new count is kept for the next repeat
+        type_keys(&mut state, &keys("."));
+        assert_eq!(lines(&state), [""]);
+    }
+
+    #[test]
+    fn repeat_insertion() {
+        let mut state = state_with_lines(&["one", "two"]);
+        type_keys(&mut state, &keys("A!!"));
+        type_keys(&mut state, &[Key::Escape, Key::Char('j'), Key::Char('.')]);
+        assert_eq!(lines(&state), ["one!!", "two!!"]);
+
+        type_keys(&mut state, &keys("0cwsix"));
+        type_keys(
+            &mut state,
+            &[Key::Escape, Key::Char('k'), Key::Char('0'), Key::Char('.')],
+        );
+        assert_eq!(lines(&state), ["six!!", "six!!"]);
+
+        type_keys(&mut state, &keys("0rxj."));
+        assert_eq!(lines(&state), ["xix!!", "xix!!"]);
+    }
+
+    #[test]
+    fn insertion_count() {
+        let mut state = state_with_lines(&["ab", "end"]);
+        type_keys(&mut state, &keys("l3ix"));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["axxxb", "end"]);
+
+        // Each repetition is on its own line
+        type_keys(&mut state, &keys("2oy"));
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!(lines(&state), ["axxxb", "y", "y", "end"]);
+        assert_eq!(state.current_line_index(), 2);
+
+        // One undo step, and the count is repeated with the change
+        type_keys(&mut state, &keys("uG."));
+        assert_eq!(lines(&state), ["axxxb", "end", "y", "y"]);
+        type_keys(&mut state, &keys("u"));
+        assert_eq!(lines(&state), ["axxxb", "end"]);
+    }
+}
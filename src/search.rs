@@ -0,0 +1,460 @@
+use std::ops::{Range, RangeInclusive};
+
+use crate::{
+    Message, MessageType, Mode, State,
+    edit::Position,
+    key::Key,
+    line::Line,
+    regex::{Regex, RegexError},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// A query ready to be matched against lines
+#[derive(Debug)]
+pub enum Pattern {
+    Literal { text: String, ignore_case: bool },
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Returns the codepoint columns of the matches in `line`, ends included
+    /// Empty matches are left out as there is nothing to highlight
+    #[must_use]
+    pub fn highlights(&self, line: &Line) -> Vec<RangeInclusive<usize>> {
+        self.find_iter(line)
+            .filter(|found| !found.is_empty())
+            .map(|found| found.start..=found.end - 1)
+            .collect()
+    }
+
+    /// Returns the codepoint range of the first match at or after `from`
+    #[must_use]
+    pub fn find(&self, line: &Line, from: usize) -> Option<Range<usize>> {
+        match self {
+            Pattern::Literal { text, ignore_case } => {
+                let start = if *ignore_case {
+                    line.find_ignore_case(text, from)
+                } else {
+                    line.find(text, from)
+                }?;
+                Some(start..start + text.chars().count())
+            }
+            Pattern::Regex(regex) => line.find_regex(regex, from),
+        }
+    }
+
+    /// Returns the codepoint ranges of the successive matches in `line`, the characters
+    /// are only collected once for a regular expression
+    /// A match after an empty one starts at least one character later
+    pub fn find_iter<'a>(&'a self, line: &'a Line) -> impl Iterator<Item = Range<usize>> + 'a {
+        let chars: Vec<char> = match self {
+            Pattern::Literal { .. } => Vec::new(),
+            Pattern::Regex(_) => line.chars().collect(),
+        };
+        let mut from = 0;
+        std::iter::from_fn(move || {
+            let found = match self {
+                Pattern::Literal { .. } => self.find(line, from),
+                Pattern::Regex(regex) => regex.find_at(&chars, from),
+            }?;
+            from = found.end + usize::from(found.is_empty());
+            Some(found)
+        })
+    }
+
+    /// Returns the codepoint index of the last match starting before `before`
+    fn rfind(&self, line: &Line, before: usize) -> Option<usize> {
+        match self {
+            Pattern::Literal {
+                text,
+                ignore_case: true,
+            } => line.rfind_ignore_case(text, before),
+            Pattern::Literal { text, .. } => line.rfind(text, before),
+            Pattern::Regex(regex) => {
+                let chars: Vec<char> = line.chars().collect();
+                let mut last = None;
+                while let Some(found) = regex.find_at(&chars, last.map_or(0, |start| start + 1))
+                    && found.start < before
+                {
+                    last = Some(found.start);
+                }
+                last
+            }
+        }
+    }
+}
+
+impl State {
+    /// Returns true if `query` matches letters whatever their case, like Vim's
+    /// `ignorecase` and `smartcase`
+    fn ignores_case(&self, query: &str) -> bool {
+        self.config.ignore_case
+            && !(self.config.smart_case && query.chars().any(char::is_uppercase))
+    }
+
+    /// Compiles `query` as a regular expression, or as literal text without `regex`
+    pub fn pattern(&self, query: &str) -> Result<Pattern, RegexError> {
+        let ignore_case = self.ignores_case(query);
+        if self.config.regex {
+            Regex::new(query, ignore_case).map(Pattern::Regex)
+        } else {
+            Ok(Pattern::Literal {
+                text: query.to_owned(),
+                ignore_case,
+            })
+        }
+    }
+
+    /// Returns the line index and codepoint column of the first match after the cursor
+    /// The search wraps around the end of the buffer
+    fn find_forward(&self, pattern: &Pattern) -> Option<(usize, usize)> {
+        let current = self.current_line_index();
+        if let Some(found) = pattern.find(&self.text_lines[current], self.cursor_pos.col + 1) {
+            return Some((current, found.start));
+        }
+
+        (1..=self.text_lines.len())
+            .map(|i| (current + i) % self.text_lines.len())
+            .find_map(|index| {
+                pattern
+                    .find(&self.text_lines[index], 0)
+                    .map(|found| (index, found.start))
+            })
+    }
+
+    /// Same as `find_forward` but looks for the closest match before the cursor
+    fn find_backward(&self, pattern: &Pattern) -> Option<(usize, usize)> {
+        let current = self.current_line_index();
+        if let Some(col) = pattern.rfind(&self.text_lines[current], self.cursor_pos.col) {
+            return Some((current, col));
+        }
+
+        let len = self.text_lines.len();
+        (1..=len)
+            .map(|i| (current + len - i) % len)
+            .find_map(|index| {
+                let line = &self.text_lines[index];
+                pattern.rfind(line, line.len()).map(|col| (index, col))
+            })
+    }
+
+    pub fn search(&mut self, query: &str, direction: Direction) {
+        self.highlight_search = true;
+        let pattern = match self.pattern(query) {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                self.message = Message {
+                    msg: format!("Invalid pattern: {err}"),
+                    r#type: MessageType::Error,
+                };
+                return;
+            }
+        };
+        let found = match direction {
+            Direction::Forward => self.find_forward(&pattern),
+            Direction::Backward => self.find_backward(&pattern),
+        };
+
+        if let Some((index, col)) = found {
+            let cursor = (self.current_line_index(), self.cursor_pos.col);
+            match direction {
+                Direction::Forward if (index, col) <= cursor => {
+                    self.message = Message {
+                        msg: "search hit BOTTOM, continuing at TOP".to_owned(),
+                        r#type: MessageType::Warning,
+                    };
+                }
+                Direction::Backward if (index, col) >= cursor => {
+                    self.message = Message {
+                        msg: "search hit TOP, continuing at BOTTOM".to_owned(),
+                        r#type: MessageType::Warning,
+                    };
+                }
+                _ => {}
+            }
+            self.push_jump();
+            self.move_to_line(index);
+            self.cursor_pos.col = col;
+            self.target_col = col;
+        } else {
+            self.message = Message {
+                msg: format!("Pattern not found: {query}"),
+                r#type: MessageType::Error,
+            };
+        }
+    }
+
+    /// Puts the cursor and the view back as they were at `origin`, then on the first
+    /// match of the query being typed
+    fn preview_search(&mut self, origin: Position, view: (usize, usize)) {
+        (self.text_offset, self.col_offset) = view;
+        self.move_to_line(origin.0);
+        self.cursor_pos.col = origin.1;
+        if self.command_buf.is_empty() {
+            return;
+        }
+        // An invalid pattern may still be incomplete
+        let Ok(pattern) = self.pattern(&self.command_buf) else {
+            return;
+        };
+
+        if let Some((index, col)) = self.find_forward(&pattern) {
+            self.move_to_line(index);
+            self.cursor_pos.col = col;
+        }
+    }
+
+    /// Compiles the query being typed, or else the last search unless highlighting was
+    /// turned off, so that a frame highlights its matches
+    pub fn search_highlight(&self) -> Option<Pattern> {
+        let query = match (&self.current_mode, &self.last_search) {
+            (Mode::Search { .. }, _) => &self.command_buf,
+            (_, Some(last)) if self.highlight_search => last,
+            _ => return None,
+        };
+        if query.is_empty() {
+            return None;
+        }
+
+        self.pattern(query).ok()
+    }
+
+    /// Repeats the last search, does nothing if there is none
+    pub fn repeat_search(&mut self, direction: Direction) {
+        if let Some(query) = self.last_search.take() {
+            self.message.clear();
+            self.search(&query, direction);
+            self.last_search = Some(query);
+        }
+    }
+
+    /// The cursor previews the first match while the query is typed
+    /// Returns true if the program should continue
+    pub fn handle_keypress_search(
+        &mut self,
+        key: &Key,
+        origin: Position,
+        view: (usize, usize),
+    ) -> bool {
+        match key {
+            Key::Char(c) => {
+                // TODO: check end of window
+                self.command_buf.push(*c);
+                self.preview_search(origin, view);
+            }
+            Key::Escape => {
+                self.current_mode = Mode::Normal;
+                self.message.clear();
+                self.command_buf.clear();
+                self.preview_search(origin, view);
+
+                return true;
+            }
+            Key::Backspace if self.command_buf.pop().is_none() => {
+                self.current_mode = Mode::Normal;
+
+                return true;
+            }
+            Key::Backspace => self.preview_search(origin, view),
+            Key::Enter => {
+                self.current_mode = Mode::Normal;
+                self.message.clear();
+
+                let query = std::mem::take(&mut self.command_buf);
+                self.preview_search(origin, view);
+                if !query.is_empty() {
+                    self.search(&query, Direction::Forward);
+                    self.last_search = Some(query);
+                }
+
+                return true;
+            }
+            _ => {}
+        }
+        self.current_mode = Mode::Search { origin, view };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use crate::{
+        State,
+        key::Key,
+        test_utils::{keys, numbered_lines, state_with_lines, type_keys},
+        theme::Background,
+    };
+
+    /// Columns highlighted in the line at `index`
+    fn matches(state: &State, index: usize) -> Vec<RangeInclusive<usize>> {
+        state.search_highlight().map_or_else(Vec::new, |pattern| {
+            pattern.highlights(&state.text_lines[index])
+        })
+    }
+
+    fn search(query: &str) -> Vec<Key> {
+        let mut keys = vec![Key::Char('/')];
+        keys.extend(query.chars().map(Key::Char));
+        keys.push(Key::Enter);
+        keys
+    }
+
+    #[test]
+    fn search_forward() {
+        let mut state = state_with_lines(&["foo bar", "été foo", "bar"]);
+        type_keys(&mut state, &search("foo"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 4));
+        assert_eq!(state.last_search.as_deref(), Some("foo"));
+
+        // Wraps to the top
+        type_keys(&mut state, &search("foo"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+
+        type_keys(&mut state, &search("missing"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+        assert!(state.message.has_message());
+    }
+
+    #[test]
+    fn incremental_search() {
+        let mut state = state_with_lines(&["foo bar", "été foo", "bar fo"]);
+        type_keys(&mut state, &[Key::Char('/'), Key::Char('f')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 4));
+        assert_eq!(matches(&state, 2), [4..=4]);
+        type_keys(&mut state, &[Key::Char('o'), Key::Char('o')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 4));
+        assert_eq!(matches(&state, 0), [0..=2]);
+        assert!(matches(&state, 2).is_empty());
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        let background = Background(state.config.theme.search_background);
+        assert!(frame.contains(&format!("  1 {background}foo\x1b[49m bar")));
+
+        // No match keeps the cursor where the search started
+        type_keys(&mut state, &[Key::Char('x')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+        type_keys(&mut state, &[Key::Backspace]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 4));
+
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+        assert!(matches(&state, 0).is_empty());
+
+        type_keys(&mut state, &search("bar"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 4));
+        type_keys(&mut state, &[Key::Ctrl('o')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+    }
+
+    #[test]
+    fn cancelled_search_keeps_view() {
+        let mut state = numbered_lines(50);
+        type_keys(&mut state, &keys("10j/line 45"));
+        assert_eq!(state.current_line_index(), 44);
+        assert_ne!(state.text_offset, 0);
+        type_keys(&mut state, &[Key::Escape]);
+        assert_eq!((state.text_offset, state.cursor_pos.row), (0, 10));
+    }
+
+    #[test]
+    fn highlight_last_search() {
+        let mut state = state_with_lines(&["foo bar foo", "bar"]);
+        type_keys(&mut state, &search("foo"));
+        assert_eq!(matches(&state, 0), [0..=2, 8..=10]);
+
+        type_keys(&mut state, &keys(":noh"));
+        type_keys(&mut state, &[Key::Enter]);
+        assert!(matches(&state, 0).is_empty());
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(!frame.contains(&Background(state.config.theme.search_background).to_string()));
+
+        // Searching again brings the highlighting back
+        type_keys(&mut state, &[Key::Char('n')]);
+        assert_eq!(matches(&state, 0), [0..=2, 8..=10]);
+        type_keys(&mut state, &search("bar"));
+        assert_eq!(matches(&state, 1), [0..=2]);
+    }
+
+    #[test]
+    fn search_case() {
+        let mut state = state_with_lines(&["Foo", "foo", "FOO"]);
+        state.config.ignore_case = true;
+        type_keys(&mut state, &search("foo"));
+        assert_eq!(state.current_line_index(), 1);
+        type_keys(&mut state, &[Key::Char('n')]);
+        assert_eq!(state.current_line_index(), 2);
+        type_keys(&mut state, &[Key::Char('N'), Key::Char('N')]);
+        assert_eq!(state.current_line_index(), 0);
+
+        // An uppercase letter makes it case sensitive
+        state.config.smart_case = true;
+        type_keys(&mut state, &search("FOO"));
+        assert_eq!(state.current_line_index(), 2);
+        type_keys(&mut state, &search("foo"));
+        assert_eq!(state.current_line_index(), 0);
+
+        // Smartcase does nothing without ignorecase
+        state.config.ignore_case = false;
+        type_keys(&mut state, &search("foo"));
+        assert_eq!(state.current_line_index(), 1);
+        type_keys(&mut state, &search("Foo"));
+        assert_eq!(state.current_line_index(), 0);
+    }
+
+    #[test]
+    fn search_regex() {
+        let mut state = state_with_lines(&["let x = 1;", "日本 y = 42;", "a.b"]);
+        type_keys(&mut state, &search(r"\d{2}"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 7));
+        type_keys(&mut state, &[Key::Char('N')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 7));
+        type_keys(&mut state, &search("^a"));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 0));
+
+        type_keys(
+            &mut state,
+            &[Key::Char('/'), Key::Char('['), Key::Char('y')],
+        );
+        // An incomplete pattern does not move the cursor nor highlight anything
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 0));
+        assert!(matches(&state, 1).is_empty());
+        type_keys(&mut state, &[Key::Char(']')]);
+        assert_eq!(matches(&state, 1), [3..=3]);
+        type_keys(&mut state, &[Key::Escape]);
+
+        type_keys(&mut state, &search("(x"));
+        assert_eq!(state.message.msg, "Invalid pattern: Unmatched parenthesis");
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 0));
+
+        // Literal search
+        state.config.regex = false;
+        type_keys(&mut state, &search("."));
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 1));
+        type_keys(&mut state, &search("(x"));
+        assert!(state.message.msg.starts_with("Pattern not found"));
+    }
+
+    #[test]
+    fn repeat_search() {
+        let mut state = state_with_lines(&["foo bar", "été foo", "bar foo"]);
+        // No previous search
+        type_keys(&mut state, &[Key::Char('n')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 0));
+
+        type_keys(&mut state, &search("foo"));
+        type_keys(&mut state, &[Key::Char('n')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 4));
+
+        type_keys(&mut state, &[Key::Char('N')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 4));
+        type_keys(&mut state, &[Key::Char('N'), Key::Char('N')]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 4));
+    }
+}
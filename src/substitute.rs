@@ -0,0 +1,165 @@
+use std::ops::Range;
+
+use crate::{Message, MessageType, State, line::Line};
+
+impl State {
+    /// Replaces `pattern` with `replacement` in the lines of `range`, only the first
+    /// occurrence of each line unless `global`
+    /// `pattern` is matched like a search, an empty one is the last search, and
+    /// `replacement` is literal text
+    pub fn substitute(
+        &mut self,
+        range: Range<usize>,
+        pattern: &str,
+        replacement: &str,
+        global: bool,
+    ) {
+        let pattern = match (pattern, &self.last_search) {
+            ("", Some(last)) => last.clone(),
+            ("", None) => {
+                self.message = Message {
+                    msg: "No previous search pattern".to_owned(),
+                    r#type: MessageType::Error,
+                };
+                return;
+            }
+            (pattern, _) => pattern.to_owned(),
+        };
+        let compiled = match self.pattern(&pattern) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                self.message = Message {
+                    msg: format!("Invalid pattern: {err}"),
+                    r#type: MessageType::Error,
+                };
+                return;
+            }
+        };
+
+        let mut substitutions = 0;
+        let mut changed_lines = 0;
+        let mut last_line = None;
+        for index in range {
+            let line = &self.text_lines[index];
+            let mut found: Vec<Range<usize>> = Vec::new();
+            for range in compiled.find_iter(line) {
+                // No empty match right after the previous one
+                if range.is_empty() && found.last().is_some_and(|last| last.end == range.start) {
+                    continue;
+                }
+                found.push(range);
+                if !global {
+                    break;
+                }
+            }
+            if found.is_empty() {
+                continue;
+            }
+
+            let chars: Vec<char> = line.chars().collect();
+            let mut text = String::with_capacity(line.as_bytes().len());
+            let mut copied = 0;
+            for range in &found {
+                text.extend(&chars[copied..range.start]);
+                text.push_str(replacement);
+                copied = range.end;
+            }
+            text.extend(&chars[copied..]);
+
+            // All the lines are changed in one undo step
+            if last_line.is_none() {
+                self.save_undo_point();
+            }
+            self.text_lines[index] = Line::with_string(text);
+            substitutions += found.len();
+            changed_lines += 1;
+            last_line = Some(index);
+        }
+
+        let Some(last_line) = last_line else {
+            self.message = Message {
+                msg: format!("Pattern not found: {pattern}"),
+                r#type: MessageType::Error,
+            };
+            return;
+        };
+
+        self.dirty = true;
+        self.move_to_line(last_line);
+        self.cursor_pos.col = self.text_lines[last_line].first_non_blank();
+        self.target_col = self.cursor_pos.col;
+        let plural = |n| if n == 1 { "" } else { "s" };
+        self.message = Message {
+            msg: format!(
+                "{substitutions} substitution{} on {changed_lines} line{}",
+                plural(substitutions),
+                plural(changed_lines)
+            ),
+            r#type: MessageType::Info,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        MessageType,
+        key::Key,
+        test_utils::{lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn substitute() {
+        let mut state = state_with_lines(&["foo foo", "  bar foo", "été"]);
+        state.substitute(0..3, "foo", "日", false);
+        assert_eq!(lines(&state), ["日 foo", "  bar 日", "été"]);
+        assert_eq!(state.message.msg, "2 substitutions on 2 lines");
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (1, 2));
+
+        state.substitute(0..1, "foo", "", true);
+        assert_eq!(lines(&state), ["日 ", "  bar 日", "été"]);
+        state.substitute(2..3, "é", "e", true);
+        assert_eq!(lines(&state), ["日 ", "  bar 日", "ete"]);
+        assert_eq!(state.message.msg, "2 substitutions on 1 line");
+
+        state.substitute(0..3, "missing", "x", true);
+        assert_eq!(state.message.msg, "Pattern not found: missing");
+        assert!(matches!(state.message.r#type, MessageType::Error));
+
+        // Everything is undone at once
+        type_keys(
+            &mut state,
+            &[Key::Char('u'), Key::Char('u'), Key::Char('u')],
+        );
+        assert_eq!(lines(&state), ["foo foo", "  bar foo", "été"]);
+    }
+
+    #[test]
+    fn substitute_regex() {
+        let mut state = state_with_lines(&["x = 12 + 345", "  日本  ", "baaa"]);
+        state.substitute(0..1, r"\d+", "n", true);
+        assert_eq!(lines(&state), ["x = n + n", "  日本  ", "baaa"]);
+        state.substitute(1..2, r"^\s+|\s+$", "", true);
+        assert_eq!(lines(&state), ["x = n + n", "日本", "baaa"]);
+        // Empty matches
+        state.substitute(2..3, "a*", "-", true);
+        assert_eq!(lines(&state), ["x = n + n", "日本", "-b-"]);
+
+        state.substitute(0..3, "[n", "x", true);
+        assert_eq!(state.message.msg, "Invalid pattern: Missing ] after [");
+        state.config.regex = false;
+        state.substitute(0..1, "n + n", "x.*", false);
+        assert_eq!(lines(&state), ["x = x.*", "日本", "-b-"]);
+    }
+
+    #[test]
+    fn substitute_last_search() {
+        let mut state = state_with_lines(&["aaaa"]);
+        state.substitute(0..1, "", "b", true);
+        assert_eq!(state.message.msg, "No previous search pattern");
+
+        state.last_search = Some("aa".to_owned());
+        state.substitute(0..1, "", "a", true);
+        assert_eq!(lines(&state), ["aa"]);
+    }
+}
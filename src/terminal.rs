@@ -0,0 +1,84 @@
+use std::{
+    io::{Read, Write, stdout},
+    time::Duration,
+};
+
+use color_eyre::eyre::Context;
+use cvt::cvt;
+use libc::{STDIN_FILENO, TCSAFLUSH, TCSANOW};
+
+use crate::key::Input;
+
+/// Keeps the terminal in raw mode with the alt buffer enabled
+/// The previous settings are restored when this is dropped
+pub struct RawTerminal {
+    previous_io_settings: libc::termios,
+}
+
+impl RawTerminal {
+    pub fn enable() -> color_eyre::Result<Self> {
+        let mut previous_io_settings: libc::termios = unsafe { std::mem::zeroed() };
+
+        cvt(unsafe { libc::tcgetattr(STDIN_FILENO, &raw mut previous_io_settings) })
+            .wrap_err("Could not get terminal parameters")?;
+
+        // cfmakeraw also clears ISIG, IXON, ICRNL and OPOST: Ctrl-C and Ctrl-S reach us as
+        // plain keys, Enter arrives as '\r' and output is never translated, so drawing
+        // must position the cursor explicitly instead of relying on newlines
+        let mut current_io_settings = previous_io_settings;
+        unsafe {
+            libc::cfmakeraw(&raw mut current_io_settings);
+        }
+        current_io_settings.c_cc[libc::VMIN] = 0;
+        current_io_settings.c_cc[libc::VTIME] = 1;
+
+        cvt(unsafe { libc::tcsetattr(STDIN_FILENO, TCSAFLUSH, &raw const current_io_settings) })
+            .wrap_err("Could not set terminal parameters")?;
+
+        let terminal = RawTerminal {
+            previous_io_settings,
+        };
+
+        let mut lock = stdout().lock();
+        // Enable alt buffer
+        lock.write_all(b"\x1b[?1049h")
+            .wrap_err("Could not write to stdout")?;
+
+        Ok(terminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut lock = stdout().lock();
+        // Show the cursor in case we stopped mid-frame, restore the default
+        // cursor shape, disable alt buffer
+        let _ = lock.write(b"\x1b[?25h\x1b[0 q\x1b[?1049l");
+        let _ = lock.flush();
+        unsafe {
+            libc::tcsetattr(STDIN_FILENO, TCSANOW, &raw const self.previous_io_settings);
+        }
+    }
+}
+
+/// Unbuffered standard input, so that polling the file descriptor sees every pending byte
+pub struct TerminalInput;
+
+impl Read for TerminalInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = cvt(unsafe { libc::read(STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) })?;
+        Ok(read.cast_unsigned())
+    }
+}
+
+impl Input for TerminalInput {
+    fn poll(&mut self, timeout: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+        unsafe { libc::poll(&raw mut fd, 1, timeout) > 0 }
+    }
+}
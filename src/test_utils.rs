@@ -0,0 +1,29 @@
+use crate::{State, WindowSize, key::Key, line::Line};
+
+pub fn state_with_lines(lines: &[&str]) -> State {
+    let lines = lines.iter().map(|&l| Line::from(l)).collect();
+    State::new(lines, None, WindowSize { col: 80, row: 24 })
+}
+
+/// A state with the lines `line 1` to `line n`
+pub fn numbered_lines(n: usize) -> State {
+    let lines = (1..=n)
+        .map(|n| Line::with_string(format!("line {n}")))
+        .collect();
+    State::new(lines, None, WindowSize { col: 80, row: 24 })
+}
+
+pub fn type_keys(state: &mut State, keys: &[Key]) {
+    for key in keys {
+        state.handle_keypress(key);
+    }
+}
+
+/// Keys typing `keys` character by character
+pub fn keys(keys: &str) -> Vec<Key> {
+    keys.chars().map(Key::Char).collect()
+}
+
+pub fn lines(state: &State) -> Vec<&str> {
+    state.text_lines.iter().map(Line::as_str).collect()
+}
@@ -0,0 +1,147 @@
+use std::fmt::Display;
+
+use crate::highlight::TokenKind;
+
+/// Red, green and blue components of a color
+pub type Rgb = (u8, u8, u8);
+
+/// Cursor shapes of the DECSCUSR escape sequence, with their parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    BlinkingBlock = 1,
+    SteadyBlock = 2,
+    BlinkingUnderline = 3,
+    SteadyUnderline = 4,
+    BlinkingBar = 5,
+    SteadyBar = 6,
+}
+
+impl CursorShape {
+    /// Returns the shape called `name`, like `bar` or `blinkbar`
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<CursorShape> {
+        match name {
+            "blinkblock" => Some(CursorShape::BlinkingBlock),
+            "block" => Some(CursorShape::SteadyBlock),
+            "blinkunderline" => Some(CursorShape::BlinkingUnderline),
+            "underline" => Some(CursorShape::SteadyUnderline),
+            "blinkbar" => Some(CursorShape::BlinkingBar),
+            "bar" => Some(CursorShape::SteadyBar),
+            _ => None,
+        }
+    }
+}
+
+/// Colors used to draw the UI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub cursor_line_background: Rgb,
+    pub status_line_background: Rgb,
+    pub status_line_foreground: Rgb,
+    pub selection_background: Rgb,
+    /// Matches of the search being typed
+    pub search_background: Rgb,
+    pub number_foreground: Rgb,
+    pub string_foreground: Rgb,
+    pub keyword_foreground: Rgb,
+}
+
+impl Theme {
+    pub const DARK: Theme = Theme {
+        cursor_line_background: (54, 58, 79),
+        status_line_background: (30, 32, 48),
+        status_line_foreground: (202, 211, 245),
+        selection_background: (91, 96, 120),
+        search_background: (120, 100, 55),
+        number_foreground: (245, 169, 127),
+        string_foreground: (166, 218, 149),
+        keyword_foreground: (198, 160, 246),
+    };
+
+    pub const LIGHT: Theme = Theme {
+        cursor_line_background: (230, 233, 239),
+        status_line_background: (204, 208, 218),
+        status_line_foreground: (76, 79, 105),
+        selection_background: (172, 176, 190),
+        search_background: (249, 226, 175),
+        number_foreground: (254, 100, 11),
+        string_foreground: (64, 160, 43),
+        keyword_foreground: (136, 57, 239),
+    };
+
+    /// Color of the text highlighted as `kind`
+    #[must_use]
+    pub const fn token_foreground(&self, kind: TokenKind) -> Rgb {
+        match kind {
+            TokenKind::Number => self.number_foreground,
+            TokenKind::String => self.string_foreground,
+            TokenKind::Keyword => self.keyword_foreground,
+        }
+    }
+
+    /// Returns the built-in theme called `name`
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::DARK),
+            "light" => Some(Theme::LIGHT),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DARK
+    }
+}
+
+/// Displays as the escape sequence setting the background color
+pub struct Background(pub Rgb);
+
+impl Display for Background {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (r, g, b) = self.0;
+        write!(f, "\x1b[48;2;{r};{g};{b}m")
+    }
+}
+
+/// Displays as the escape sequence setting the foreground color
+pub struct Foreground(pub Rgb);
+
+impl Display for Foreground {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (r, g, b) = self.0;
+        write!(f, "\x1b[38;2;{r};{g};{b}m")
+    }
+}
+
+/// Displays as the escape sequence setting the cursor shape
+impl Display for CursorShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\x1b[{} q", *self as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::theme::{Background, CursorShape, Foreground, Theme};
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(
+            Background(Theme::DARK.cursor_line_background).to_string(),
+            "\x1b[48;2;54;58;79m"
+        );
+        assert_eq!(Foreground((1, 2, 3)).to_string(), "\x1b[38;2;1;2;3m");
+        assert_eq!(CursorShape::SteadyBar.to_string(), "\x1b[6 q");
+        assert_eq!(CursorShape::BlinkingBlock.to_string(), "\x1b[1 q");
+    }
+
+    #[test]
+    fn by_name() {
+        assert_eq!(Theme::by_name("light"), Some(Theme::LIGHT));
+        assert_eq!(Theme::by_name("dark"), Some(Theme::default()));
+        assert_eq!(Theme::by_name("solarized"), None);
+    }
+}
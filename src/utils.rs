@@ -1,12 +1,84 @@
 use std::{
-    fs::OpenOptions,
-    io::{BufWriter, Write},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
     path::Path,
 };
 
 use crate::line::Line;
 
-pub fn save_to_file<P: AsRef<Path>>(path: P, lines: &[Line]) -> std::io::Result<()> {
+/// Lines of a file read for editing
+#[derive(Debug)]
+pub struct FileContent {
+    pub lines: Vec<Line>,
+    /// The last line ended with a newline, also true for an empty file
+    pub end_of_line: bool,
+    /// Describes what was read
+    pub info: String,
+}
+
+/// Splits the content of `reader` into lines, `\r\n` line endings are normalized
+/// Also returns whether the last line ended with a newline
+pub fn read_lines<R: BufRead>(mut reader: R) -> std::io::Result<(Vec<Line>, bool)> {
+    let mut lines = Vec::new();
+    let mut end_of_line = true;
+    let mut bytes = Vec::new();
+    while reader.read_until(b'\n', &mut bytes)? > 0 {
+        end_of_line = bytes.last() == Some(&b'\n');
+        if end_of_line {
+            bytes.pop();
+            if bytes.last() == Some(&b'\r') {
+                bytes.pop();
+            }
+        }
+        lines.push(Line::from_bytes(std::mem::take(&mut bytes)));
+    }
+
+    Ok((lines, end_of_line))
+}
+
+/// Reads the file at `path` for editing, a missing file is an empty new buffer
+pub fn load_file(path: &Path) -> std::io::Result<FileContent> {
+    let name = path.display();
+    match File::open(path) {
+        Ok(f) => {
+            let metadata = f.metadata()?;
+            // Reading a directory fails on every line, which would never end
+            if metadata.is_dir() {
+                return Err(ErrorKind::IsADirectory.into());
+            }
+            let size = metadata.len();
+            let (lines, end_of_line) = read_lines(BufReader::new(f))?;
+            let mut info = format!("\"{name}\" ");
+            if !end_of_line {
+                info.push_str("[noeol] ");
+            }
+            if lines.iter().any(Line::is_converted) {
+                info.push_str("[converted] ");
+            }
+            info.push_str(&format!("{}L, {size}B", lines.len()));
+            Ok(FileContent {
+                lines,
+                end_of_line,
+                info,
+            })
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(FileContent {
+            lines: Vec::new(),
+            end_of_line: true,
+            info: format!("\"{name}\" [New]"),
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `lines` to the file at `path`, the last one is followed by a newline only
+/// with `end_of_line`
+/// Returns the number of bytes written
+pub fn save_to_file<P: AsRef<Path>>(
+    path: P,
+    lines: &[Line],
+    end_of_line: bool,
+) -> std::io::Result<usize> {
     let file = OpenOptions::new()
         .write(true)
         .create(true)
@@ -14,10 +86,114 @@ pub fn save_to_file<P: AsRef<Path>>(path: P, lines: &[Line]) -> std::io::Result<
         .open(path)?;
 
     let mut writer = BufWriter::new(file);
-    for line in lines {
-        writer.write_all(line.as_bytes())?;
-        writer.write_all(b"\n")?;
+    let mut written = 0;
+    for (i, line) in lines.iter().enumerate() {
+        writer.write_all(line.file_bytes())?;
+        written += line.file_bytes().len();
+        if end_of_line || i + 1 < lines.len() {
+            writer.write_all(b"\n")?;
+            written += 1;
+        }
     }
+    writer.flush()?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, ErrorKind, Read};
+
+    use crate::utils::{load_file, read_lines, save_to_file};
+
+    #[test]
+    fn read_lines_endings() {
+        let (lines, end_of_line) = read_lines("first\nsecond\n".as_bytes()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].as_str(), "second");
+        assert!(end_of_line);
+
+        let (lines, end_of_line) = read_lines("first\nno trailing newline".as_bytes()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].as_str(), "no trailing newline");
+        assert!(!end_of_line);
+
+        let (lines, _) = read_lines("windows\r\nline\r\n".as_bytes()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].as_str(), "windows");
+        assert_eq!(lines[1].as_str(), "line");
 
-    Ok(())
+        let (lines, end_of_line) = read_lines("".as_bytes()).unwrap();
+        assert!(lines.is_empty());
+        assert!(end_of_line);
+    }
+
+    #[test]
+    fn load_file_info() {
+        let path = std::env::temp_dir().join(format!("vim-rs-load-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let content = load_file(&path).unwrap();
+        assert!(content.lines.is_empty());
+        assert_eq!(content.info, format!("\"{}\" [New]", path.display()));
+
+        let (lines, _) = read_lines("one\ntwo\n".as_bytes()).unwrap();
+        save_to_file(&path, &lines, true).unwrap();
+        let content = load_file(&path).unwrap();
+        assert_eq!(content.lines.len(), 2);
+        assert_eq!(content.info, format!("\"{}\" 2L, 8B", path.display()));
+
+        save_to_file(&path, &lines, false).unwrap();
+        let content = load_file(&path).unwrap();
+        assert_eq!(
+            content.info,
+            format!("\"{}\" [noeol] 2L, 7B", path.display())
+        );
+
+        let _ = std::fs::remove_file(path);
+        let err = load_file(&std::env::temp_dir()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::IsADirectory);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let path = std::env::temp_dir().join(format!("vim-rs-latin1-{}", std::process::id()));
+        std::fs::write(&path, b"caf\xe9\nok\n\xff\xfe").unwrap();
+
+        let mut content = load_file(&path).unwrap();
+        assert_eq!(
+            content.info,
+            format!("\"{}\" [noeol] [converted] 3L, 10B", path.display())
+        );
+        assert_eq!(content.lines[0].as_str(), "caf\u{fffd}");
+        assert_eq!(content.lines[1].as_str(), "ok");
+
+        // The bytes of unchanged lines are kept
+        content.lines[2].push('!');
+        save_to_file(&path, &content.lines, true).unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"caf\xe9\nok\n\xef\xbf\xbd\xef\xbf\xbd!\n"
+        );
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn read_error() {
+        /// Fails after the first line
+        struct Failing(bool);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if std::mem::replace(&mut self.0, true) {
+                    return Err(ErrorKind::InvalidData.into());
+                }
+                buf[..6].copy_from_slice(b"first\n");
+                Ok(6)
+            }
+        }
+
+        let err = read_lines(BufReader::new(Failing(false))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
 }
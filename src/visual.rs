@@ -0,0 +1,227 @@
+use crate::{
+    Mode, State, WindowSize, edit::Position, key::Key, keymap::Action, register::RegisterContent,
+};
+
+impl State {
+    /// Returns the start and end of the selection, in buffer order
+    /// A line-wise selection goes from the start of its first line to `usize::MAX` on its last
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let Mode::Visual { anchor, linewise } = &self.current_mode else {
+            return None;
+        };
+        let cursor = (self.current_line_index(), self.cursor_pos.col);
+        let anchor = (anchor.row, anchor.col);
+        if *linewise {
+            Some((
+                (cursor.0.min(anchor.0), 0),
+                (cursor.0.max(anchor.0), usize::MAX),
+            ))
+        } else {
+            Some((cursor.min(anchor), cursor.max(anchor)))
+        }
+    }
+
+    /// Selects characters, or whole lines with `linewise` like `V`
+    pub fn enable_visual_mode(&mut self, linewise: bool) {
+        self.message.clear();
+        self.current_mode = Mode::Visual {
+            anchor: WindowSize {
+                col: self.cursor_pos.col,
+                row: self.current_line_index(),
+            },
+            linewise,
+        };
+    }
+
+    /// Moves the cursor to `pos` and goes back to Normal mode
+    fn leave_visual_mode(&mut self, pos: Position) {
+        self.current_mode = Mode::Normal;
+        self.move_to_line(pos.0);
+        let len = self.get_current_line().map_or(0, |l| l.len());
+        self.cursor_pos.col = pos.1.min(len.saturating_sub(1));
+        self.target_col = self.cursor_pos.col;
+    }
+
+    /// Returns true if the program should continue
+    pub fn handle_keypress_visual(
+        &mut self,
+        key: &Key,
+        anchor: WindowSize,
+        linewise: bool,
+    ) -> bool {
+        let anchor_col = anchor.col;
+        self.current_mode = Mode::Visual { anchor, linewise };
+
+        if self.accumulate_count(key) {
+            return true;
+        }
+        let count = self.pending_count.take();
+        let Some(&action) = self.keymap.visual.get(key) else {
+            return true;
+        };
+        if self.handle_motion(action, count) {
+            return true;
+        }
+
+        let Some((start, end)) = self.selection() else {
+            return true;
+        };
+        match action {
+            Action::ExitVisualMode => {
+                self.current_mode = Mode::Normal;
+            }
+            // The key of the current kind of selection leaves it, the other switches to it
+            Action::VisualMode | Action::VisualLineMode => {
+                let switched = action == Action::VisualLineMode;
+                if let Mode::Visual { linewise, .. } = &mut self.current_mode
+                    && *linewise != switched
+                {
+                    *linewise = switched;
+                } else {
+                    self.current_mode = Mode::Normal;
+                }
+            }
+            Action::DeleteSelection if linewise => {
+                self.leave_visual_mode(start);
+                self.delete_lines(end.0 - start.0 + 1);
+            }
+            Action::YankSelection if linewise => {
+                let lines = self.text_lines[start.0..=end.0].to_vec();
+                self.set_register(RegisterContent::Lines(lines));
+                // The column is kept when the cursor is on the first line
+                let col = if self.current_line_index() == start.0 {
+                    self.cursor_pos.col
+                } else {
+                    anchor_col
+                };
+                self.leave_visual_mode((start.0, col));
+            }
+            Action::DeleteSelection => {
+                self.save_undo_point();
+                let text = self.delete_range(start, end);
+                self.set_register(RegisterContent::Chars(text));
+                self.leave_visual_mode(start);
+            }
+            Action::YankSelection => {
+                let text = self.text_in_range(start, end);
+                self.set_register(RegisterContent::Chars(text));
+                self.leave_visual_mode(start);
+            }
+            _ => {}
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        register::RegisterContent,
+        test_utils::{lines, state_with_lines, type_keys},
+    };
+
+    #[test]
+    fn delete_selection() {
+        let mut state = state_with_lines(&["hello wörld"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('$'),
+                Key::Char('v'),
+                Key::Char('h'),
+                Key::Char('h'),
+                Key::Char('d'),
+            ],
+        );
+        assert_eq!(lines(&state), ["hello wö"]);
+        assert_eq!(state.cursor_pos.col, 7);
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "rld"));
+    }
+
+    #[test]
+    fn line_selection() {
+        let mut state = state_with_lines(&["one", "two", "three", "four"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('l'),
+                Key::Char('V'),
+                Key::Char('j'),
+                Key::Char('d'),
+            ],
+        );
+        assert_eq!(lines(&state), ["one", "four"]);
+        assert_eq!(state.current_line_index(), 1);
+        assert!(matches!(
+            &state.register,
+            Some(RegisterContent::Lines(lines)) if lines.len() == 2 && lines[1].as_str() == "three"
+        ));
+        type_keys(&mut state, &[Key::Char('P')]);
+        assert_eq!(lines(&state), ["one", "two", "three", "four"]);
+
+        // Selecting upward
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('G'),
+                Key::Char('$'),
+                Key::Char('V'),
+                Key::Char('k'),
+                Key::Char('y'),
+            ],
+        );
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (2, 4));
+        type_keys(
+            &mut state,
+            &[Key::Char('g'), Key::Char('g'), Key::Char('p')],
+        );
+        assert_eq!(
+            lines(&state),
+            ["one", "three", "four", "two", "three", "four"]
+        );
+    }
+
+    #[test]
+    fn switch_selection_kind() {
+        let mut state = state_with_lines(&["abc", "def"]);
+        type_keys(
+            &mut state,
+            &[Key::Char('l'), Key::Char('v'), Key::Char('j')],
+        );
+        assert_eq!(state.selection(), Some(((0, 1), (1, 1))));
+        type_keys(&mut state, &[Key::Char('V')]);
+        assert_eq!(state.selection(), Some(((0, 0), (1, usize::MAX))));
+        let frame = String::from_utf8(state.render().unwrap()).unwrap();
+        assert!(frame.contains("-- VISUAL LINE --"));
+        type_keys(&mut state, &[Key::Char('v')]);
+        assert_eq!(state.selection(), Some(((0, 1), (1, 1))));
+        type_keys(&mut state, &[Key::Char('v')]);
+        assert_eq!(state.selection(), None);
+    }
+
+    #[test]
+    fn yank_selection_backwards() {
+        let mut state = state_with_lines(&["first", "second"]);
+        type_keys(
+            &mut state,
+            &[
+                Key::Char('j'),
+                Key::Char('l'),
+                Key::Char('v'),
+                Key::Char('k'),
+                Key::Char('l'),
+                Key::Char('y'),
+            ],
+        );
+        assert_eq!(lines(&state), ["first", "second"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 2));
+        assert!(matches!(&state.register, Some(RegisterContent::Chars(text)) if text == "rst\nse"));
+
+        type_keys(&mut state, &[Key::Char('$'), Key::Char('p')]);
+        assert_eq!(lines(&state), ["firstrst", "se", "second"]);
+        assert_eq!((state.current_line_index(), state.cursor_pos.col), (0, 5));
+    }
+}